@@ -1,35 +1,93 @@
 use proc_macro2::TokenStream;
-use quote::{quote, TokenStreamExt};
+use quote::{format_ident, quote, TokenStreamExt};
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::{Component, Path};
 use std::str::FromStr;
 use tiled::PropertyValue;
 
 static LEVEL_NAMES: &[&str] = &["level1", "level2"];
 
+/// Where the levels sit relative to each other, so the player can walk from one
+/// to the next
+static WORLD_FILE: &str = "maps/whitch.world";
+
+/// The background colour of every level
+static TRANSPARENT_COLOUR: &str = "1e151b";
+
+/// Every .wav in here is a sound effect. Files named the same apart from a
+/// trailing number, like Jump1.wav and Jump2.wav, are variations of one sound.
+static SFX_DIR: &str = "sfx";
+
+/// Optional volume, priority and pitch for each sound, by name
+static SOUND_MANIFEST: &str = "sfx/sounds.json";
+
+/// The save data keeps a bit for each pickup in a level
+const MAX_PICKUPS: usize = 32;
+
+/// Checkpoints keep a bit for each entity in a level that has been defeated
+const MAX_ENTITIES: usize = 64;
+
+static SPRITE_FILES: &[&str] = &[
+    "gfx/whitch_design.aseprite",
+    "gfx/enemies.aseprite",
+    "gfx/objects.aseprite",
+];
+
 fn main() {
     let out_dir = env::var("OUT_DIR").expect("OUT_DIR environment variable must be specified");
 
     let mut tile_loader = tiled::Loader::new();
+    // Tilesets can be shared between maps, so watch all of them
+    println!("cargo:rerun-if-changed=maps");
+    // Set to print how much ROM each level's tilemaps take
+    println!("cargo:rerun-if-env-changed=WHITCH_TILEMAP_REPORT");
+
+    let mut tileset_images = TilesetImages::default();
+    let mut levels = LEVEL_NAMES
+        .iter()
+        .map(|level| load_level(&mut tile_loader, &mut tileset_images, level))
+        .collect::<Vec<_>>();
 
-    let levels = LEVEL_NAMES
+    let sizes = levels
         .iter()
-        .map(|x| x.to_string())
-        .map(|level| load_level(&mut tile_loader, &format!("./maps/levels/{level}.tmx")))
+        .map(|level| (level.1.width as i32 * 8, level.1.height as i32 * 8))
         .collect::<Vec<_>>();
-    let levels_tiles = levels.iter().map(|level| &level.0);
+    let world = World::load(WORLD_FILE, &sizes);
+    for (level_number, level) in levels.iter_mut().enumerate() {
+        level.1.neighbours = world.neighbours(level_number);
+    }
+
+    let gfx = tileset_images.include_gfx();
+    let levels_graphics = levels.iter().map(|level| &level.0);
     let levels_data = levels.iter().map(|level| &level.1);
 
     let tilemaps_output = quote! {
-        use agb::display::tiled::TileSetting;
-        pub static LEVEL_LAYER_TILESETTINGS: &[&[&[TileSetting]]] = &[#(#levels_tiles),*];
+        use agb::display::Priority;
+        use agb::fixnum::{Num, Vector2D};
+        use agb::include_background_gfx;
+
+        #gfx
+
+        pub static LEVEL_GRAPHICS: &[LevelGraphics] = &[#(#levels_graphics),*];
     };
 
     let levels_output = quote! {
         pub static LEVELS: &[Level] = &[#(#levels_data),*];
     };
 
+    let sounds = export_sounds();
+
+    let sprite_boxes = SPRITE_FILES
+        .iter()
+        .flat_map(|filename| export_sprite_boxes(filename));
+
+    let sprite_boxes_output = quote! {
+        #(#sprite_boxes)*
+    };
+
     {
         let tilemaps_output_file = File::create(format!("{out_dir}/tilemaps.rs"))
             .expect("Failed to open tilemaps.rs for writing");
@@ -44,14 +102,48 @@ fn main() {
 
         write!(&mut levels_output_writer, "{levels_output}").unwrap();
     }
+
+    {
+        let sounds_output_file = File::create(format!("{out_dir}/sounds.rs"))
+            .expect("Failed to open sounds.rs for writing");
+        let mut sounds_writer = BufWriter::new(sounds_output_file);
+
+        write!(&mut sounds_writer, "{sounds}").unwrap();
+    }
+
+    {
+        let sprite_boxes_output_file = File::create(format!("{out_dir}/sprite_boxes.rs"))
+            .expect("Failed to open sprite_boxes.rs for writing");
+        let mut sprite_boxes_writer = BufWriter::new(sprite_boxes_output_file);
+
+        write!(&mut sprite_boxes_writer, "{sprite_boxes_output}").unwrap();
+    }
 }
 
-fn load_level<'a>(loader: &'a mut tiled::Loader, filename: &'a str) -> (TokenStream, Level) {
-    let level_map = load_tmx(loader, filename);
-    let tiles = export_backgrounds(&level_map, quote!(level));
-    let data = export_level(&level_map);
+fn load_level(
+    loader: &mut tiled::Loader,
+    tileset_images: &mut TilesetImages,
+    level: &str,
+) -> (TokenStream, Level) {
+    let level_map = load_tmx(loader, &format!("./maps/levels/{level}.tmx"));
+    let tilesets = LevelTilesets::new(&level_map, tileset_images);
+    let bounds = MapBounds::new(&level_map);
+    let (graphics, bytes) = export_graphics(&level_map, &tilesets, bounds);
+    let data = export_level(&level_map, bounds);
 
-    (tiles, data)
+    if env::var_os("WHITCH_TILEMAP_REPORT").is_some() {
+        let layers = level_map
+            .layers()
+            .filter(|layer| layer.as_tile_layer().is_some())
+            .count();
+        let unpacked = data.width as usize * data.height as usize * layers * 2;
+        println!(
+            "cargo:warning={level}: {}x{} tiles, {layers} layers, tilemaps take {bytes} bytes ({unpacked} unpacked)",
+            data.width, data.height
+        );
+    }
+
+    (graphics, data)
 }
 
 fn load_tmx(loader: &mut tiled::Loader, filename: &str) -> tiled::Map {
@@ -62,6 +154,19 @@ fn load_tmx(loader: &mut tiled::Loader, filename: &str) -> tiled::Map {
 enum EntityType {
     Player,
     Bat,
+    Checkpoint,
+    Coin,
+    Potion,
+    Key,
+}
+
+impl EntityType {
+    fn is_pickup(&self) -> bool {
+        matches!(
+            self,
+            EntityType::Coin | EntityType::Potion | EntityType::Key
+        )
+    }
 }
 
 impl FromStr for EntityType {
@@ -73,6 +178,10 @@ impl FromStr for EntityType {
         Ok(match s {
             "PLAYER" => Player,
             "BAT" => Bat,
+            "CHECKPOINT" => Checkpoint,
+            "COIN" => Coin,
+            "POTION" => Potion,
+            "KEY" => Key,
             _ => return Err(()),
         })
     }
@@ -85,6 +194,37 @@ impl quote::ToTokens for EntityType {
         tokens.append_all(match self {
             Bat => quote!(EntityType::Bat),
             Player => quote!(EntityType::Player),
+            Checkpoint => quote!(EntityType::Checkpoint),
+            Coin => quote!(EntityType::Coin),
+            Potion => quote!(EntityType::Potion),
+            Key => quote!(EntityType::Key),
+        })
+    }
+}
+
+enum Music {
+    Crawl,
+}
+
+impl FromStr for Music {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Music::*;
+
+        Ok(match s {
+            "CRAWL" => Crawl,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl quote::ToTokens for Music {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        use Music::*;
+
+        tokens.append_all(match self {
+            Crawl => quote!(Music::Crawl),
         })
     }
 }
@@ -122,16 +262,135 @@ impl FromStr for Behavior {
     }
 }
 
+/// What happens to an entity when the player respawns at a checkpoint
+enum Respawn {
+    /// Back at its starting position, even if it was defeated
+    Always,
+    /// Stays gone once defeated
+    Never,
+}
+
+impl FromStr for Respawn {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Respawn::*;
+
+        Ok(match s {
+            "ALWAYS" => Always,
+            "NEVER" => Never,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl quote::ToTokens for Respawn {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        use Respawn::*;
+
+        tokens.append_all(match self {
+            Always => quote!(Respawn::Always),
+            Never => quote!(Respawn::Never),
+        })
+    }
+}
+
 struct Entity(
     EntityType,
     (i32, i32),
     Option<(i32, i32)>,
     Vec<Behavior>,
-    (i32, i32),
+    Respawn,
+    /// Keeps simulating when far from the camera
+    bool,
 );
 
 struct CollisionRect((i32, i32), (i32, i32));
 
+/// A level that shares an edge with another, and where it is relative to it
+struct Neighbour {
+    level: usize,
+    area: CollisionRect,
+}
+
+impl quote::ToTokens for Neighbour {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let level = self.level;
+        let area = &self.area;
+        tokens.append_all(quote! {
+            Neighbour { level: #level, area: #area }
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct WorldFile {
+    maps: Vec<WorldMap>,
+}
+
+#[derive(serde::Deserialize)]
+struct WorldMap {
+    #[serde(rename = "fileName")]
+    file_name: String,
+    x: i32,
+    y: i32,
+}
+
+/// The position in pixels of every level placed in the world, by level number
+struct World {
+    areas: Vec<Option<CollisionRect>>,
+}
+
+impl World {
+    fn load(filename: &str, sizes: &[(i32, i32)]) -> Self {
+        println!("cargo:rerun-if-changed={filename}");
+        let file = File::open(filename).expect("Failed to open the world file");
+        let world: WorldFile =
+            serde_json::from_reader(file).expect("Failed to parse the world file");
+
+        let areas = LEVEL_NAMES
+            .iter()
+            .zip(sizes)
+            .map(|(level, size)| {
+                let file_name = format!("levels/{level}.tmx");
+                world
+                    .maps
+                    .iter()
+                    .find(|map| map.file_name == file_name)
+                    .map(|map| CollisionRect((map.x, map.y), *size))
+            })
+            .collect();
+
+        Self { areas }
+    }
+
+    /// Every level touching an edge of the given level, with their area in its
+    /// coordinates
+    fn neighbours(&self, level_number: usize) -> Vec<Neighbour> {
+        let Some(CollisionRect((x, y), (width, height))) = self.areas[level_number] else {
+            return Vec::new();
+        };
+
+        self.areas
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != level_number)
+            .filter_map(|(other, area)| Some((other, area.as_ref()?)))
+            .filter(|(_, CollisionRect((ox, oy), (ow, oh)))| {
+                let overlaps_x = *ox < x + width && x < ox + ow;
+                let overlaps_y = *oy < y + height && y < oy + oh;
+                let touches_x = *ox == x + width || ox + ow == x;
+                let touches_y = *oy == y + height || oy + oh == y;
+                (touches_x && overlaps_y) || (touches_y && overlaps_x)
+            })
+            .map(|(other, CollisionRect((ox, oy), size))| Neighbour {
+                level: other,
+                area: CollisionRect((ox - x, oy - y), *size),
+            })
+            .collect()
+    }
+}
+
 enum CollisionShape {
     Solid(CollisionRect),
     Door(CollisionRect),
@@ -148,13 +407,11 @@ impl quote::ToTokens for Entity {
             None => quote!(None),
         };
         let behaviors = &self.3;
-
-        let offset_x = &self.4 .0;
-        let offset_y = &self.4 .1;
-        let sprite_offset = quote!(Vector2D::new(#offset_x, #offset_y));
+        let respawn = &self.4;
+        let always_active = self.5;
 
         tokens.append_all(
-            quote!(Entity(#entity_type, #location, #size, &[#(#behaviors),*], #sprite_offset)),
+            quote!(Entity(#entity_type, #location, #size, &[#(#behaviors),*], #respawn, #always_active)),
         )
     }
 }
@@ -180,6 +437,12 @@ impl<'a> quote::ToTokens for Level {
         let starting_positions = &self.starting_positions;
         let name = &self.name;
         let collision_rects = &self.collision_rects;
+        let camera_locks = &self.camera_locks;
+        let neighbours = &self.neighbours;
+        let music = match &self.music {
+            Some(music) => quote!(Some(#music)),
+            None => quote!(None),
+        };
 
         tokens.append_all(quote! {
             Level::new(
@@ -188,6 +451,9 @@ impl<'a> quote::ToTokens for Level {
                 &[#(#starting_positions),*],
                 #name,
                 &[#(#collision_rects),*],
+                &[#(#camera_locks),*],
+                &[#(#neighbours),*],
+                #music,
             )
         })
     }
@@ -206,50 +472,405 @@ impl<'a> quote::ToTokens for CollisionShape {
     }
 }
 
-fn export_backgrounds(map: &tiled::Map, level: TokenStream) -> TokenStream {
-    let ground_tiles = export_tiles(map, "ground".to_owned(), &level);
-    let bg_tiles = export_tiles(map, "bg".to_owned(), &level);
+/// Module holding the graphics of every tileset image, shared by all the
+/// levels. Having one module means every image is converted once, however many
+/// levels use it. Every Tiled tileset becomes a deduplicated image, plus a copy
+/// without deduplication when it has animated tiles so that their graphics can
+/// be swapped without affecting identical looking tiles.
+///
+/// The images in one module share one set of palettes, and tiles refer to
+/// palettes by their index in that set, so every level uploads the whole set.
+/// That is at most 16 palettes, 512 bytes, which is cheap next to the tiles,
+/// and only the tiles a level's tilesets use are loaded.
+const TILESET_MODULE: &str = "tileset_gfx";
+
+/// Every tileset image the levels use, each included once
+#[derive(Default)]
+struct TilesetImages {
+    /// The name in the generated module, the path and whether identical tiles
+    /// are merged
+    images: Vec<(proc_macro2::Ident, String, bool)>,
+}
+
+impl TilesetImages {
+    /// The index of the image, adding it if no level has used it yet
+    fn add(&mut self, path: &str, deduplicate: bool) -> usize {
+        if let Some(index) = self
+            .images
+            .iter()
+            .position(|(_, other, other_deduplicate)| {
+                other == path && *other_deduplicate == deduplicate
+            })
+        {
+            return index;
+        }
+
+        let stem = Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("Tileset images should have a utf-8 file name")
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+        let suffix = if deduplicate { "" } else { "_animated" };
+        let mut name = format!("{stem}{suffix}");
+        // Images with the same file name in different folders
+        if self.images.iter().any(|(other, _, _)| *other == name) {
+            name = format!("{name}_{}", self.images.len());
+        }
+
+        self.images
+            .push((format_ident!("{}", name), path.to_owned(), deduplicate));
+        self.images.len() - 1
+    }
+
+    fn include_gfx(&self) -> TokenStream {
+        let module = format_ident!("{}", TILESET_MODULE);
+        let images = self.images.iter().map(|(name, path, deduplicate)| {
+            if *deduplicate {
+                quote!(#name => deduplicate #path)
+            } else {
+                quote!(#name => #path)
+            }
+        });
+
+        quote! {
+            include_background_gfx!(#module, #TRANSPARENT_COLOUR, #(#images),*);
+        }
+    }
+}
+
+/// The tileset images one level uses
+struct LevelTilesets {
+    /// The name of each image in the shared module. Cells store their index
+    /// in this list.
+    images: Vec<proc_macro2::Ident>,
+    /// The index into `images` of the static and animated version of each
+    /// Tiled tileset
+    indices: Vec<(usize, Option<usize>)>,
+}
+
+impl LevelTilesets {
+    fn new(map: &tiled::Map, tileset_images: &mut TilesetImages) -> Self {
+        let mut images = Vec::new();
+        let mut indices = Vec::new();
+
+        let mut use_image = |path: &str, deduplicate: bool| {
+            let image = tileset_images.add(path, deduplicate);
+            let name = &tileset_images.images[image].0;
+            images
+                .iter()
+                .position(|other| other == name)
+                .unwrap_or_else(|| {
+                    images.push(name.clone());
+                    images.len() - 1
+                })
+        };
+
+        for tileset in map.tilesets() {
+            let image = tileset.image.as_ref().unwrap_or_else(|| {
+                panic!("The tileset {} should use a single image", tileset.name)
+            });
+            let image_path = normalise_path(&image.source);
+
+            let static_index = use_image(&image_path, true);
+            let animated = tileset.tiles().any(|(_, tile)| tile.animation.is_some());
+            let animated_index = animated.then(|| use_image(&image_path, false));
+
+            indices.push((static_index, animated_index));
+        }
+
+        assert!(
+            images.len() <= MAX_LEVEL_TILESETS,
+            "A level should use at most {MAX_LEVEL_TILESETS} tilesets, counting animated ones twice"
+        );
+
+        Self { images, indices }
+    }
+
+    fn tile_data(&self, index: usize) -> TokenStream {
+        let module = format_ident!("{}", TILESET_MODULE);
+        let name = &self.images[index];
+        quote!(#module::#name)
+    }
+}
+
+/// A path with any `..` components resolved, as `include_background_gfx!`
+/// expects paths relative to the crate root
+fn normalise_path(path: &Path) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(part) => parts.push(part.to_str().expect("Paths should be utf-8")),
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+fn export_graphics(
+    map: &tiled::Map,
+    tilesets: &LevelTilesets,
+    bounds: MapBounds,
+) -> (TokenStream, usize) {
+    let module = format_ident!("{}", TILESET_MODULE);
+    let tile_data = (0..tilesets.images.len()).map(|index| tilesets.tile_data(index));
+    let (layers, bytes) = export_backgrounds(map, tilesets, bounds);
+    let animations = export_tile_animations(map, tilesets);
+
+    let graphics = quote! {
+        LevelGraphics {
+            palettes: #module::PALETTES,
+            tilesets: &[#(&#tile_data),*],
+            layers: #layers,
+            animations: #animations,
+        }
+    };
 
-    quote! {&[#ground_tiles, #bg_tiles]}
+    (graphics, bytes)
 }
 
-fn export_tiles(map: &tiled::Map, layer_name: String, level: &TokenStream) -> TokenStream {
-    let layer = map
+/// Every tile layer of the map, topmost first so that layers sharing a
+/// priority get the lower numbered (frontmost) background.
+///
+/// Parallax and offset come from Tiled's own layer attributes. The priority
+/// comes from an optional `priority` property on the layer, defaulting to in
+/// front of the sprites for layers above the `entities` layer and behind them
+/// otherwise.
+fn export_backgrounds(
+    map: &tiled::Map,
+    tilesets: &LevelTilesets,
+    bounds: MapBounds,
+) -> (TokenStream, usize) {
+    let entities_index = map
         .layers()
-        .find(|l| l.name == layer_name)
-        .expect(format!("The {} layer should exist", layer_name).as_str());
+        .position(|l| l.name == "entities")
+        .expect("The entities layer should exist");
+
+    let layers = map
+        .layers()
+        .enumerate()
+        .filter(|(_, layer)| layer.as_tile_layer().is_some())
+        .map(|(index, layer)| {
+            let default_priority = if index > entities_index { 0 } else { 2 };
+            export_tile_layer(layer, default_priority, tilesets, bounds)
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        (1..=4).contains(&layers.len()),
+        "A level should have between 1 and 4 tile layers"
+    );
+
+    let bytes = layers.iter().map(|(_, bytes)| bytes).sum();
+    let layers = layers.iter().rev().map(|(layer, _)| layer);
+    (quote! {&[#(#layers),*]}, bytes)
+}
+
+fn export_tile_layer(
+    layer: tiled::Layer,
+    default_priority: i32,
+    tilesets: &LevelTilesets,
+    bounds: MapBounds,
+) -> (TokenStream, usize) {
+    let layer_name = layer.name.clone();
+
+    let priority = match layer.properties.get("priority") {
+        Some(PropertyValue::IntValue(priority)) => *priority,
+        Some(_) => panic!("The priority of layer {layer_name} should be an int"),
+        None => default_priority,
+    };
+    let priority = match priority {
+        0 => quote!(Priority::P0),
+        1 => quote!(Priority::P1),
+        2 => quote!(Priority::P2),
+        3 => quote!(Priority::P3),
+        _ => panic!("The priority of layer {layer_name} should be between 0 and 3"),
+    };
+
+    let parallax_x = fixed_raw(layer.parallax_x);
+    let parallax_y = fixed_raw(layer.parallax_y);
+    let offset_x = layer.offset_x as i32;
+    let offset_y = layer.offset_y as i32;
+
+    let edge = string_property(&layer, "edge");
+    let edge_x = edge_policy(
+        &layer_name,
+        string_property(&layer, "edge_x").or(edge.clone()),
+    );
+    let edge_y = edge_policy(&layer_name, string_property(&layer, "edge_y").or(edge));
+
+    let (cells, bytes) = export_tiles(layer, tilesets, bounds);
+
+    let layer = quote! {
+        TileLayer {
+            cells: #cells,
+            parallax: Vector2D::new(Num::from_raw(#parallax_x), Num::from_raw(#parallax_y)),
+            offset: Vector2D::new(#offset_x, #offset_y),
+            priority: #priority,
+            edge_x: #edge_x,
+            edge_y: #edge_y,
+        }
+    };
+
+    (layer, bytes)
+}
 
+fn string_property(layer: &tiled::Layer, name: &str) -> Option<String> {
+    match layer.properties.get(name) {
+        Some(PropertyValue::StringValue(value)) => Some(value.clone()),
+        Some(_) => panic!(
+            "The {name} property of layer {} should be a string",
+            layer.name
+        ),
+        None => None,
+    }
+}
+
+/// What a layer shows past the edge of the level, clamping to the edge tiles by
+/// default so the player never sees a hard cut off
+fn edge_policy(layer_name: &str, edge: Option<String>) -> TokenStream {
+    match edge.as_deref() {
+        None | Some("clamp") => quote!(Edge::Clamp),
+        Some("blank") => quote!(Edge::Blank),
+        Some("wrap") => quote!(Edge::Wrap),
+        Some(edge) => panic!(
+            "Unknown edge {edge} on layer {layer_name}, should be one of blank, clamp or wrap"
+        ),
+    }
+}
+
+/// The raw value of a `FixedNum<8>`
+fn fixed_raw(value: f32) -> i32 {
+    (value * 256.0).round() as i32
+}
+
+/// Width and height of a chunk of cells. Identical chunks, such as empty sky,
+/// are only stored once.
+const CHUNK_SIZE: u32 = 8;
+
+/// The top 4 bits of a cell pick the tileset, with the last value saved for
+/// `BLANK_CELL`
+const MAX_LEVEL_TILESETS: usize = 15;
+
+/// A cell with no tile. Never a real tile as tileset 15 is not allowed.
+const BLANK_CELL: u16 = 0xffff;
+
+/// Pack a cell as the tile id in the low 10 bits, hflip and vflip in the next
+/// two and the index of the level's tileset in the top 4
+fn pack_cell(tile_id: u32, hflip: bool, vflip: bool, tileset: usize) -> u16 {
+    assert!(tile_id < 1024, "Tilesets should have at most 1024 tiles");
+    assert!(
+        tileset < MAX_LEVEL_TILESETS,
+        "A level should use at most {MAX_LEVEL_TILESETS} tilesets"
+    );
+    tile_id as u16 | (hflip as u16) << 10 | (vflip as u16) << 11 | (tileset as u16) << 12
+}
+
+/// The layer's cells split into deduplicated chunks, along with how many bytes
+/// of ROM they take
+fn export_tiles(
+    layer: tiled::Layer,
+    tilesets: &LevelTilesets,
+    bounds: MapBounds,
+) -> (TokenStream, usize) {
+    let layer_name = layer.name.clone();
     let map_tiles = layer
         .as_tile_layer()
         .expect(format!("The {} layer should be a tile layer", layer_name).as_str());
 
-    let width = map_tiles.width().expect("Map should be finite");
-    let height = map_tiles.height().unwrap();
+    let width = bounds.width;
+    let height = bounds.height;
 
-    let map_tiles = (0..(height * width))
-        .map(|pos| (pos % width, pos / width))
-        .map(|(x, y)| {
-            let tile = map_tiles.get_tile(x as i32, y as i32);
+    let cell = |x: u32, y: u32| {
+        if x >= width || y >= height {
+            return BLANK_CELL;
+        }
 
-            match tile {
-                Some(tile) => {
-                    let tile_id = tile.id();
-                    let vflip = tile.flip_v;
-                    let hflip = tile.flip_h;
+        match map_tiles.get_tile(x as i32 + bounds.x, y as i32 + bounds.y) {
+            Some(tile) => {
+                let (static_index, animated_index) = tilesets.indices[tile.tileset_index()];
+                let animated = tile.get_tile().is_some_and(|tile| tile.animation.is_some());
+                let index = match animated_index {
+                    Some(animated_index) if animated => animated_index,
+                    _ => static_index,
+                };
+                pack_cell(tile.id(), tile.flip_h, tile.flip_v, index)
+            }
+            None => BLANK_CELL,
+        }
+    };
 
-                    quote! {
-                        backgrounds::#level.tile_settings[#tile_id as usize]
-                            .hflip(#hflip)
-                            .vflip(#vflip)
-                    }
-                }
-                None => {
-                    quote! { TileSetting::BLANK }
-                }
+    let chunks_wide = width.div_ceil(CHUNK_SIZE);
+    let chunks_high = height.div_ceil(CHUNK_SIZE);
+
+    let mut chunks: Vec<Vec<u16>> = Vec::new();
+    let mut chunk_map = Vec::new();
+    for chunk_y in 0..chunks_high {
+        for chunk_x in 0..chunks_wide {
+            let chunk = (0..CHUNK_SIZE * CHUNK_SIZE)
+                .map(|i| {
+                    cell(
+                        chunk_x * CHUNK_SIZE + i % CHUNK_SIZE,
+                        chunk_y * CHUNK_SIZE + i / CHUNK_SIZE,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            let index = chunks.iter().position(|c| *c == chunk).unwrap_or_else(|| {
+                chunks.push(chunk);
+                chunks.len() - 1
+            });
+            chunk_map.push(index as u16);
+        }
+    }
+
+    let bytes = chunk_map.len() * 2 + chunks.len() * (CHUNK_SIZE * CHUNK_SIZE) as usize * 2;
+    let chunks = chunks.iter().map(|chunk| quote!([#(#chunk),*]));
+    let chunks_wide = chunks_wide as u16;
+
+    (
+        quote! {
+            TileCells {
+                chunks_wide: #chunks_wide,
+                chunk_map: &[#(#chunk_map),*],
+                chunks: &[#(#chunks),*],
             }
-        });
+        },
+        bytes,
+    )
+}
 
-    quote! {&[#(#map_tiles),*]}
+/// The frames of every animated tile in the level's tilesets, with Tiled's
+/// durations converted from milliseconds to frames
+fn export_tile_animations(map: &tiled::Map, tilesets: &LevelTilesets) -> TokenStream {
+    let animations = map
+        .tilesets()
+        .iter()
+        .zip(&tilesets.indices)
+        .filter_map(|(tileset, (_, animated_index))| Some((tileset, (*animated_index)? as u8)))
+        .flat_map(|(tileset, tileset_index)| {
+            tileset.tiles().filter_map(move |(tile_id, tile)| {
+                let frames = tile.animation.as_ref()?.iter().map(|frame| {
+                    let frame_tile = frame.tile_id as u16;
+                    let duration = (frame.duration * 60 / 1000).max(1) as u16;
+                    quote!(AnimationFrame { tile: #frame_tile, duration: #duration })
+                });
+                let tile_id = tile_id as u16;
+
+                Some(quote! {
+                    TileAnimation {
+                        tileset: #tileset_index,
+                        tile: #tile_id,
+                        frames: &[#(#frames),*],
+                    }
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    quote! {&[#(#animations),*]}
 }
 
 struct Level {
@@ -258,9 +879,87 @@ struct Level {
     starting_positions: Vec<Entity>,
     name: String,
     collision_rects: Vec<CollisionShape>,
+    camera_locks: Vec<CollisionRect>,
+    neighbours: Vec<Neighbour>,
+    music: Option<Music>,
+}
+
+/// The part of the map covered by tiles, in tiles. Infinite maps can have
+/// content anywhere, including at negative coordinates, so the level is shifted
+/// to put the top left of its content at the origin.
+#[derive(Clone, Copy)]
+struct MapBounds {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    tile_width: i32,
+    tile_height: i32,
 }
 
-fn export_level(map: &tiled::Map) -> Level {
+impl MapBounds {
+    fn new(map: &tiled::Map) -> Self {
+        let tile_width = map.tile_width as i32;
+        let tile_height = map.tile_height as i32;
+
+        if !map.infinite() {
+            return Self {
+                x: 0,
+                y: 0,
+                width: map.width,
+                height: map.height,
+                tile_width,
+                tile_height,
+            };
+        }
+
+        let used_tiles = map
+            .layers()
+            .filter_map(|layer| match layer.as_tile_layer() {
+                Some(tiled::TileLayer::Infinite(layer)) => Some(layer),
+                _ => None,
+            })
+            .flat_map(|layer| {
+                layer.chunks().flat_map(|((chunk_x, chunk_y), chunk)| {
+                    let width = tiled::ChunkData::WIDTH as i32;
+                    let height = tiled::ChunkData::HEIGHT as i32;
+                    (0..width * height)
+                        .map(move |i| (i % width, i / width))
+                        .filter(move |&(x, y)| chunk.get_tile(x, y).is_some())
+                        .map(move |(x, y)| (chunk_x * width + x, chunk_y * height + y))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let min_x = used_tiles
+            .iter()
+            .map(|(x, _)| *x)
+            .min()
+            .expect("The map should have tiles");
+        let max_x = used_tiles.iter().map(|(x, _)| *x).max().unwrap();
+        let min_y = used_tiles.iter().map(|(_, y)| *y).min().unwrap();
+        let max_y = used_tiles.iter().map(|(_, y)| *y).max().unwrap();
+
+        Self {
+            x: min_x,
+            y: min_y,
+            width: (max_x - min_x + 1) as u32,
+            height: (max_y - min_y + 1) as u32,
+            tile_width,
+            tile_height,
+        }
+    }
+
+    /// Move a point in the map, in pixels, to where it is in the level
+    fn shift(&self, x: f32, y: f32) -> (i32, i32) {
+        (
+            (x - (self.x * self.tile_width) as f32) as i32,
+            (y - (self.y * self.tile_height) as f32) as i32,
+        )
+    }
+}
+
+fn export_level(map: &tiled::Map, bounds: MapBounds) -> Level {
     let entity_layer = map
         .layers()
         .find(|layer| layer.name == "entities")
@@ -289,44 +988,58 @@ fn export_level(map: &tiled::Map) -> Level {
                     None => Vec::new(),
                 };
 
-                let offset_x = obj
-                    .properties
-                    .get("offset_x")
-                    .map(|p| match p {
-                        PropertyValue::IntValue(x) => *x,
-                        _ => panic!("offset_x should be an int value"),
-                    })
-                    .unwrap_or(0);
-                let offset_y = obj
-                    .properties
-                    .get("offset_y")
-                    .map(|p| match p {
-                        PropertyValue::IntValue(y) => *y,
-                        _ => panic!("offset_x should be an int value"),
-                    })
-                    .unwrap_or(0);
+                let respawn = match obj.properties.get("respawn") {
+                    Some(PropertyValue::StringValue(respawn)) => respawn
+                        .parse()
+                        .unwrap_or_else(|_| panic!("unknown respawn policy {respawn}")),
+                    Some(_) => panic!("respawn should be a string value"),
+                    None => Respawn::Always,
+                };
+
+                let always_active = match obj.properties.get("always_active") {
+                    Some(PropertyValue::BoolValue(always_active)) => *always_active,
+                    Some(_) => panic!("always_active should be a bool value"),
+                    None => false,
+                };
 
                 match obj.shape {
                     tiled::ObjectShape::Rect { width, height } => Some(Entity(
                         entity_type,
-                        (obj.x as i32, obj.y as i32),
+                        bounds.shift(obj.x, obj.y),
                         Some((width as i32, height as i32)),
                         behaviors,
-                        (offset_x, offset_y),
+                        respawn,
+                        always_active,
                     )),
                     tiled::ObjectShape::Point(x, y) => Some(Entity(
                         entity_type,
-                        (x as i32, y as i32),
+                        bounds.shift(x, y),
                         None,
                         behaviors,
-                        (offset_x, offset_y),
+                        respawn,
+                        always_active,
                     )),
                     _ => None,
                 }
             }
             _ => None,
         })
-        .collect();
+        .collect::<Vec<_>>();
+
+    assert!(
+        starting_positions.len() <= MAX_ENTITIES,
+        "{} entities in one level, but checkpoints only track {MAX_ENTITIES}",
+        starting_positions.len()
+    );
+
+    let pickups = starting_positions
+        .iter()
+        .filter(|entity| entity.0.is_pickup())
+        .count();
+    assert!(
+        pickups <= MAX_PICKUPS,
+        "{pickups} pickups in one level, but only {MAX_PICKUPS} can be saved"
+    );
 
     let collision_layer = map
         .layers()
@@ -338,31 +1051,333 @@ fn export_level(map: &tiled::Map) -> Level {
         .objects()
         .into_iter()
         .map(|obj| match (&obj.shape, obj.user_type.as_str()) {
-            (tiled::ObjectShape::Rect { width, height }, "DOOR") => {
-                CollisionShape::Door(CollisionRect(
-                    (obj.x as i32, obj.y as i32),
-                    (*width as i32, *height as i32),
-                ))
-            }
-            (tiled::ObjectShape::Rect { width, height }, _) => {
-                CollisionShape::Solid(CollisionRect(
-                    (obj.x as i32, obj.y as i32),
-                    (*width as i32, *height as i32),
-                ))
-            }
+            (tiled::ObjectShape::Rect { width, height }, "DOOR") => CollisionShape::Door(
+                CollisionRect(bounds.shift(obj.x, obj.y), (*width as i32, *height as i32)),
+            ),
+            (tiled::ObjectShape::Rect { width, height }, _) => CollisionShape::Solid(
+                CollisionRect(bounds.shift(obj.x, obj.y), (*width as i32, *height as i32)),
+            ),
             _ => panic!("expected rectangles only"),
         })
         .collect();
 
+    let camera_locks = map
+        .layers()
+        .find(|layer| layer.name == "camera")
+        .and_then(|layer| layer.as_object_layer())
+        .map(|camera_layer| {
+            camera_layer
+                .objects()
+                .map(|obj| match obj.shape {
+                    tiled::ObjectShape::Rect { width, height } => {
+                        CollisionRect(bounds.shift(obj.x, obj.y), (width as i32, height as i32))
+                    }
+                    _ => panic!("camera lock regions should be rectangles"),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
     let Some(tiled::PropertyValue::StringValue(level_name)) = map.properties.get("NAME") else {
         panic!("Level property 'NAME' must be a string")
     };
 
+    let music = match map.properties.get("MUSIC") {
+        Some(tiled::PropertyValue::StringValue(music)) => Some(
+            music
+                .parse()
+                .unwrap_or_else(|_| panic!("unknown music {music}")),
+        ),
+        Some(_) => panic!("Level property 'MUSIC' must be a string"),
+        None => None,
+    };
+
     Level {
-        width: map.width,
-        height: map.height,
+        width: bounds.width,
+        height: bounds.height,
         starting_positions,
         name: level_name.clone(),
         collision_rects,
+        camera_locks,
+        neighbours: Vec::new(),
+        music,
+    }
+}
+
+struct BoxRect((i32, i32), (u32, u32));
+
+struct FrameBoxes {
+    pivot: (i32, i32),
+    hurtbox: Option<BoxRect>,
+    hitbox: Option<BoxRect>,
+}
+
+impl quote::ToTokens for BoxRect {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let (x, y) = self.0;
+        let width = self.1 .0 as i32;
+        let height = self.1 .1 as i32;
+        tokens.append_all(quote! {
+            Rect { position: Vector2D::new(#x, #y), size: Vector2D::new(#width, #height) }
+        })
+    }
+}
+
+impl quote::ToTokens for FrameBoxes {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let (pivot_x, pivot_y) = self.pivot;
+        let hurtbox = match &self.hurtbox {
+            Some(rect) => quote!(Some(#rect)),
+            None => quote!(None),
+        };
+        let hitbox = match &self.hitbox {
+            Some(rect) => quote!(Some(#rect)),
+            None => quote!(None),
+        };
+
+        tokens.append_all(quote! {
+            FrameBoxes {
+                pivot: Vector2D::new(#pivot_x, #pivot_y),
+                hurtbox: #hurtbox,
+                hitbox: #hitbox,
+            }
+        })
+    }
+}
+
+fn load_aseprite(filename: &str) -> asefile::AsepriteFile {
+    println!("cargo:rerun-if-changed={filename}");
+    asefile::AsepriteFile::read_file(Path::new(filename)).expect("failed to load aseprite file")
+}
+
+/// Export the `hurtbox`, `hitbox` and `pivot` slices of every tag in the file,
+/// one entry per frame in the order the tag plays them.
+fn export_sprite_boxes(filename: &str) -> Vec<TokenStream> {
+    let ase = load_aseprite(filename);
+    let width = ase.width() as i32;
+    let height = ase.height() as i32;
+
+    let slice_key = |name: &str, frame: u32| {
+        ase.slices()
+            .iter()
+            .find(|slice| slice.name == name)
+            .and_then(|slice| {
+                slice
+                    .keys
+                    .iter()
+                    .filter(|key| key.from_frame <= frame)
+                    .max_by_key(|key| key.from_frame)
+            })
+            .filter(|key| key.size.0 > 0 && key.size.1 > 0)
+    };
+
+    (0..ase.num_tags())
+        .map(|tag_id| {
+            let tag = ase.tag(tag_id);
+            let name = format_ident!("{}", tag.name());
+
+            let frame_order = tag_frame_order(tag);
+            // `SpriteBoxes::frame` wraps the animation frame around the table
+            assert!(
+                !frame_order.is_empty(),
+                "tag {} in {filename} has no frames",
+                tag.name()
+            );
+
+            let frames = frame_order.into_iter().map(|frame| FrameBoxes {
+                pivot: slice_key("pivot", frame)
+                    .map(|key| {
+                        let (pivot_x, pivot_y) = key.pivot.unwrap_or((0, 0));
+                        (key.origin.0 + pivot_x, key.origin.1 + pivot_y)
+                    })
+                    .unwrap_or((width / 2, height)),
+                hurtbox: slice_key("hurtbox", frame).map(|key| BoxRect(key.origin, key.size)),
+                hitbox: slice_key("hitbox", frame).map(|key| BoxRect(key.origin, key.size)),
+            });
+
+            quote! {
+                pub static #name: &SpriteBoxes = &SpriteBoxes {
+                    size: Vector2D::new(#width, #height),
+                    frames: &[#(#frames),*],
+                };
+            }
+        })
+        .collect()
+}
+
+/// The aseprite frames of a tag in the order agb's `Tag::animation_sprite` plays them
+fn tag_frame_order(tag: &asefile::Tag) -> Vec<u32> {
+    let forward = tag.from_frame()..=tag.to_frame();
+
+    match tag.animation_direction() {
+        asefile::AnimationDirection::Forward => forward.collect(),
+        asefile::AnimationDirection::Reverse => forward.rev().collect(),
+        asefile::AnimationDirection::PingPong => {
+            let len_sub_1 = (tag.to_frame() - tag.from_frame()) as usize;
+            if len_sub_1 == 0 {
+                return vec![tag.from_frame()];
+            }
+
+            (0..len_sub_1 * 2)
+                .map(|idx| {
+                    let offset =
+                        ((idx + len_sub_1) % (len_sub_1 * 2)) as isize - len_sub_1 as isize;
+                    tag.from_frame() + offset.unsigned_abs() as u32
+                })
+                .collect()
+        }
+    }
+}
+
+/// Settings for one sound from the manifest. Anything left out uses the default.
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SoundSettings {
+    volume: f32,
+    priority: u8,
+    max_instances: u8,
+    ducks_music: bool,
+    /// Lowest and highest playback speed picked at random each time it plays
+    pitch: (f32, f32),
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            priority: 0,
+            max_instances: 2,
+            ducks_music: false,
+            pitch: (1.0, 1.0),
+        }
+    }
+}
+
+/// Every .wav file in `SFX_DIR` grouped into sounds by name without the
+/// trailing number, with the files in each sound sorted
+fn sound_files() -> Vec<(String, Vec<String>)> {
+    println!("cargo:rerun-if-changed={SFX_DIR}");
+
+    let mut files = std::fs::read_dir(SFX_DIR)
+        .expect("Failed to read the sfx directory")
+        .map(|entry| entry.expect("Failed to read the sfx directory").path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "wav"))
+        .map(|path| normalise_path(&path))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut sounds: Vec<(String, Vec<String>)> = Vec::new();
+    for file in files {
+        let stem = Path::new(&file)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .expect("Sound file names must be valid UTF-8");
+        let name = stem
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .to_owned();
+        assert!(
+            !name.is_empty(),
+            "Sound file {file} needs a name, not just a number"
+        );
+
+        match sounds.iter_mut().find(|(other, _)| *other == name) {
+            Some((_, variations)) => variations.push(file),
+            None => sounds.push((name, vec![file])),
+        }
+    }
+
+    sounds
+}
+
+fn load_sound_manifest() -> HashMap<String, SoundSettings> {
+    println!("cargo:rerun-if-changed={SOUND_MANIFEST}");
+    match File::open(SOUND_MANIFEST) {
+        Ok(file) => serde_json::from_reader(file).expect("Failed to parse the sound manifest"),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// The `Sound` enum with one variant per sound and the samples and settings
+/// for each
+fn export_sounds() -> TokenStream {
+    let sounds = sound_files();
+    let mut manifest = load_sound_manifest();
+
+    let mut statics = Vec::new();
+    let mut variants = Vec::new();
+    let mut infos = Vec::new();
+    for (name, files) in &sounds {
+        let settings = manifest.remove(name).unwrap_or_default();
+        assert!(
+            settings.pitch.0 > 0.0 && settings.pitch.0 <= settings.pitch.1,
+            "Sound {name} has a pitch range that is empty or not above zero"
+        );
+
+        let samples = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let sample = format_ident!("{}_{}", screaming_snake_case(name), index);
+                statics.push(quote! {
+                    static #sample: &[u8] = agb::include_wav!(#file);
+                });
+                sample
+            })
+            .collect::<Vec<_>>();
+
+        let variant = format_ident!("{}", name);
+        let volume = fixed_raw(settings.volume) as i16;
+        let priority = settings.priority;
+        let max_instances = settings.max_instances;
+        let ducks_music = settings.ducks_music;
+        let pitch_min = fixed_raw(settings.pitch.0) as u32;
+        let pitch_max = fixed_raw(settings.pitch.1) as u32;
+
+        variants.push(variant.clone());
+        infos.push(quote! {
+            SoundInfo {
+                variations: &[#(#samples),*],
+                volume: Num::from_raw(#volume),
+                priority: #priority,
+                max_instances: #max_instances,
+                ducks_music: #ducks_music,
+                pitch: (Num::from_raw(#pitch_min), Num::from_raw(#pitch_max)),
+            }
+        });
+    }
+
+    if let Some(name) = manifest.keys().next() {
+        panic!("Sound manifest has settings for {name} but there is no {name}.wav in {SFX_DIR}");
+    }
+
+    quote! {
+        use agb::fixnum::Num;
+
+        #(#statics)*
+
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum Sound {
+            #(#variants),*
+        }
+
+        static SOUNDS: &[SoundInfo] = &[#(#infos),*];
+
+        impl Sound {
+            pub fn info(self) -> &'static SoundInfo {
+                &SOUNDS[self as usize]
+            }
+        }
+    }
+}
+
+/// `BatFlap` to `BAT_FLAP`
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if index > 0 && c.is_ascii_uppercase() {
+            result.push('_');
+        }
+        result.push(c.to_ascii_uppercase());
     }
+    result
 }
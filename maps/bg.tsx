@@ -2,6 +2,12 @@
 <tileset version="1.10" tiledversion="1.10.2" name="bg" tilewidth="8" tileheight="8" tilecount="1024" columns="32">
  <transformations hflip="0" vflip="0" rotate="0" preferuntransformed="1"/>
  <image source="../gfx/bg.png" trans="ff00ff" width="256" height="256"/>
+ <tile id="6">
+  <animation>
+   <frame tileid="6" duration="600"/>
+   <frame tileid="7" duration="600"/>
+  </animation>
+ </tile>
  <tile id="34" probability="1.5"/>
  <tile id="37" probability="1.5"/>
  <tile id="38" probability="1.5"/>
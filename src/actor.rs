@@ -1,3 +1,4 @@
+use crate::sprite_boxes::{FrameBoxes, SpriteBoxes};
 use agb::display::object::Tag;
 use agb::fixnum::{num, FixedNum, Rect, Vector2D};
 use agb::hash_map::HashMap;
@@ -5,12 +6,20 @@ use agb::input::Tri;
 
 type Number = FixedNum<8>;
 
+/// Frames an actor ignores further damage for after being hit
+const INVULNERABLE_FRAMES: u16 = 60;
+
+/// How long an attack's hitbox is out for
+const ATTACK_FRAMES: u16 = 16;
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
 pub enum ActorState {
     Idle,
     Jumping,
     Falling,
     Running,
+    /// Shown over the other states while an attack is out
+    Attacking,
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -23,11 +32,12 @@ pub enum Action {
 
 pub struct Actor<'a> {
     pub tags: HashMap<ActorState, &'a Tag>,
+    pub boxes: HashMap<ActorState, &'a SpriteBoxes>,
     pub velocity: Vector2D<Number>,
     pub acceleration: Vector2D<Number>,
     pub max_velocity: Vector2D<Number>,
     pub collision_mask: Rect<Number>,
-    pub sprite_offset: Vector2D<Number>,
+    pub animation_frame: usize,
     pub visible: bool,
     pub state: ActorState,
     pub current_action: Action,
@@ -36,21 +46,25 @@ pub struct Actor<'a> {
     pub jump_height: Number,
     pub jump_time: Number,
     pub jump_distance_to_peak: Number,
+    pub invulnerable_frames: u16,
+    /// Frames left of the current attack
+    pub attack_frames: u16,
     health: Number,
 }
 
 impl<'a> Actor<'a> {
     pub fn new(
         tags: HashMap<ActorState, &'a Tag>,
+        boxes: HashMap<ActorState, &'a SpriteBoxes>,
         position: Vector2D<Number>,
         maybe_size: Option<Vector2D<Number>>,
-        offset: Vector2D<Number>,
         max_velocity: Option<Vector2D<Number>>,
         acceleration: Option<Vector2D<Number>>,
+        health: Number,
     ) -> Self {
         Self {
             tags,
-            sprite_offset: offset,
+            boxes,
             velocity: (0, 0).into(),
             acceleration: acceleration.unwrap_or((num!(0.2), num!(0.2)).into()),
             max_velocity: max_velocity.unwrap_or((1, 1).into()),
@@ -61,6 +75,7 @@ impl<'a> Actor<'a> {
                 },
                 |size| Rect { position, size },
             ),
+            animation_frame: 0,
             visible: true,
             state: ActorState::Idle,
             current_action: Action::None,
@@ -69,7 +84,9 @@ impl<'a> Actor<'a> {
             jump_distance_to_peak: 0.into(),
             direction_x: Tri::Zero,
             facing: Tri::Zero,
-            health: num!(100.0),
+            invulnerable_frames: 0,
+            attack_frames: 0,
+            health,
         }
     }
 
@@ -150,7 +167,105 @@ impl<'a> Actor<'a> {
         })
     }
 
-    pub fn take_damage(&mut self) {
+    /// Returns whether the damage was taken, or ignored because the actor was
+    /// recently hit.
+    pub fn take_damage(&mut self) -> bool {
+        if self.invulnerable_frames > 0 {
+            return false;
+        }
+
         self.health -= 1;
+        self.invulnerable_frames = INVULNERABLE_FRAMES;
+        true
+    }
+
+    /// Start an attack unless one is already out
+    pub fn attack(&mut self) {
+        if self.attack_frames == 0 {
+            self.attack_frames = ATTACK_FRAMES;
+        }
+    }
+
+    /// The state to animate and take boxes from, which is the attack while
+    /// one is out and the actor has a tag for it
+    pub fn shown_state(&self) -> ActorState {
+        if self.attack_frames > 0 && self.tags.contains_key(&ActorState::Attacking) {
+            ActorState::Attacking
+        } else {
+            self.state
+        }
+    }
+
+    pub fn health(&self) -> Number {
+        self.health
+    }
+
+    pub fn set_health(&mut self, health: Number) {
+        self.health = health;
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.into()
+    }
+
+    pub fn is_flipped(&self) -> bool {
+        self.facing == Tri::Negative
+    }
+
+    fn frame_boxes(&self) -> Option<(&'a SpriteBoxes, &'a FrameBoxes)> {
+        self.boxes
+            .get(&self.shown_state())
+            .map(|sprite_boxes| (*sprite_boxes, sprite_boxes.frame(self.animation_frame)))
+    }
+
+    /// Top left of the sprite in world space, placing the frame's pivot at the
+    /// bottom centre of the collision mask.
+    pub fn sprite_position(&self) -> Vector2D<Number> {
+        let Rect { position, size } = self.collision_mask;
+        let anchor = position + Vector2D::new(size.x / 2, size.y);
+
+        match self.frame_boxes() {
+            Some((sprite_boxes, frame)) => {
+                anchor - sprite_boxes.pivot(frame, self.is_flipped()).into()
+            }
+            None => position,
+        }
+    }
+
+    fn frame_rect_to_world(&self, rect: Rect<i32>) -> Rect<Number> {
+        let rect = match self.frame_boxes() {
+            Some((sprite_boxes, _)) => sprite_boxes.mirror(rect, self.is_flipped()),
+            None => rect,
+        };
+
+        Rect {
+            position: self.sprite_position() + rect.position.into(),
+            size: rect.size.into(),
+        }
+    }
+
+    /// The area other actors can hit, or the collision mask if the current
+    /// frame has no hurtbox.
+    pub fn hurtbox(&self) -> Rect<Number> {
+        self.frame_boxes()
+            .and_then(|(_, frame)| frame.hurtbox)
+            .map_or(self.collision_mask, |rect| self.frame_rect_to_world(rect))
+    }
+
+    pub fn hitbox(&self) -> Option<Rect<Number>> {
+        self.frame_boxes()
+            .and_then(|(_, frame)| frame.hitbox)
+            .map(|rect| self.frame_rect_to_world(rect))
+    }
+
+    /// Whether this actor's hitbox overlaps the other actor's hurtbox
+    pub fn hits(&self, other: &Actor) -> bool {
+        self.hitbox()
+            .is_some_and(|hitbox| hitbox.touches(other.hurtbox()))
+    }
+
+    /// Whether the actors overlap closely enough to be in contact
+    pub fn touches(&self, other: &Actor) -> bool {
+        self.hurtbox().touches(other.hurtbox()) || self.hits(other) || other.hits(self)
     }
 }
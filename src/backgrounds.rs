@@ -1,68 +1,285 @@
 use crate::level::Level;
+use agb::display::palette16::Palette16;
+use agb::display::tile_data::TileData;
 use agb::display::tiled::InfiniteScrolledMap;
+use agb::display::tiled::RegularBackgroundSize;
 use agb::display::tiled::TileFormat;
+use agb::display::tiled::TileSetting;
 use agb::display::tiled::Tiled0;
+use agb::display::tiled::VRamManager;
 use agb::display::Priority;
-use agb::{
-    display::tiled::{RegularBackgroundSize, VRamManager},
-    include_background_gfx,
-};
+use agb::fixnum::FixedNum;
+use agb::fixnum::Vector2D;
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-include_background_gfx!(backgrounds, "1e151b",
-    level => deduplicate "gfx/bg.png",
-);
+type Number = FixedNum<8>;
+
+/// The background graphics of a level, converted by build.rs from the
+/// tilesets its .tmx file uses
+pub struct LevelGraphics {
+    /// The palettes of every tileset image, not just this level's, as tiles
+    /// refer to them by their index in the shared set
+    pub palettes: &'static [Palette16],
+    pub tilesets: &'static [&'static TileData],
+    pub layers: &'static [TileLayer],
+    pub animations: &'static [TileAnimation],
+}
+
+/// A tile layer exported from Tiled by build.rs
+pub struct TileLayer {
+    pub cells: TileCells,
+    /// How fast the layer scrolls relative to the camera
+    pub parallax: Vector2D<Number>,
+    /// Where the layer is drawn relative to the level, in pixels
+    pub offset: Vector2D<i32>,
+    pub priority: Priority,
+    /// What to show beyond the left and right edges of the level
+    pub edge_x: Edge,
+    /// What to show beyond the top and bottom edges of the level
+    pub edge_y: Edge,
+}
+
+/// How a layer looks past the edge of the level, set with the `edge`,
+/// `edge_x` or `edge_y` properties on the layer in Tiled
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edge {
+    /// Nothing is drawn
+    Blank,
+    /// The tiles along the edge are repeated
+    Clamp,
+    /// The layer repeats, for skies and other backdrops
+    Wrap,
+}
+
+impl Edge {
+    /// The cell to show for `position` on an axis `length` cells long, if any
+    fn resolve(self, position: i32, length: i32) -> Option<usize> {
+        if (0..length).contains(&position) {
+            return Some(position as usize);
+        }
+
+        match self {
+            Edge::Blank => None,
+            Edge::Clamp => Some(position.clamp(0, length - 1) as usize),
+            Edge::Wrap => Some(position.rem_euclid(length) as usize),
+        }
+    }
+}
+
+const CHUNK_SIZE: usize = 8;
+const BLANK_CELL: u16 = 0xffff;
+
+/// The tiles of a layer, split into 8x8 chunks so that repeated chunks are only
+/// stored once. Each cell packs the tile id in the low 10 bits, hflip and vflip
+/// in the next two and which of the level's tilesets it is from in the top 4.
+pub struct TileCells {
+    pub chunks_wide: u16,
+    /// The index into `chunks` of each chunk of the layer, row by row
+    pub chunk_map: &'static [u16],
+    pub chunks: &'static [[u16; CHUNK_SIZE * CHUNK_SIZE]],
+}
+
+impl TileCells {
+    fn cell(&self, x: usize, y: usize) -> u16 {
+        let chunk_index = x / CHUNK_SIZE + (y / CHUNK_SIZE) * self.chunks_wide as usize;
+        let chunk = &self.chunks[self.chunk_map[chunk_index] as usize];
+        chunk[x % CHUNK_SIZE + (y % CHUNK_SIZE) * CHUNK_SIZE]
+    }
+
+    fn tile(
+        &self,
+        graphics: &'static LevelGraphics,
+        x: usize,
+        y: usize,
+    ) -> (&'static TileData, TileSetting) {
+        let cell = self.cell(x, y);
+        if cell == BLANK_CELL {
+            return (graphics.tilesets[0], TileSetting::BLANK);
+        }
+
+        let tileset = graphics.tilesets[(cell >> 12) as usize & 0xf];
+
+        let setting = tileset.tile_settings[(cell & 0x3ff) as usize]
+            .hflip(cell & (1 << 10) != 0)
+            .vflip(cell & (1 << 11) != 0);
+        (tileset, setting)
+    }
+}
+
+/// A tile whose graphics cycle through `frames`
+pub struct TileAnimation {
+    /// The level tileset holding the tile and its frames
+    pub tileset: u8,
+    pub tile: u16,
+    pub frames: &'static [AnimationFrame],
+}
+
+pub struct AnimationFrame {
+    pub tile: u16,
+    /// How long to show this frame for, in frames
+    pub duration: u16,
+}
+
+impl TileAnimation {
+    fn tile_at(&self, frame: usize) -> u16 {
+        let length: usize = self.frames.iter().map(|f| f.duration as usize).sum();
+        let mut time = frame % length.max(1);
+        for animation_frame in self.frames {
+            if time < animation_frame.duration as usize {
+                return animation_frame.tile;
+            }
+            time -= animation_frame.duration as usize;
+        }
+        self.tile
+    }
+}
 
 mod tilemaps {
-    use super::backgrounds;
+    use super::{AnimationFrame, Edge, LevelGraphics, TileAnimation, TileCells, TileLayer};
     include!(concat!(env!("OUT_DIR"), "/tilemaps.rs"));
 }
 
-pub fn load_palettes(vram_manager: &mut VRamManager) {
-    vram_manager.set_background_palettes(backgrounds::PALETTES);
+pub fn load_palettes(vram_manager: &mut VRamManager, level_number: usize) {
+    vram_manager.set_background_palettes(tilemaps::LEVEL_GRAPHICS[level_number].palettes);
 }
 
-pub fn load_backgrounds<'a>(
-    level_number: usize,
-    level: &'a Level,
+struct ParallaxLayer<'a> {
+    map: InfiniteScrolledMap<'a>,
+    layer: &'static TileLayer,
+}
+
+impl ParallaxLayer<'_> {
+    fn set_scroll(
+        &mut self,
+        vram: &mut VRamManager,
+        scroll_pos: Vector2D<i32>,
+        shake: Vector2D<i32>,
+    ) {
+        let parallax = self.layer.parallax;
+        let position = Vector2D {
+            x: (Number::from(scroll_pos.x) * parallax.x).floor(),
+            y: (Number::from(scroll_pos.y) * parallax.y).floor(),
+        };
+        self.map
+            .set_pos(vram, -(position + shake) - self.layer.offset);
+    }
+}
+
+/// The tile layers of a level, each on its own background
+pub struct LevelBackgrounds<'a> {
+    layers: Vec<ParallaxLayer<'a>>,
+    graphics: &'static LevelGraphics,
+    frame: usize,
+}
+
+impl LevelBackgrounds<'_> {
+    pub fn init(&mut self, vram: &mut VRamManager, between_updates: &mut impl FnMut()) {
+        for layer in self.layers.iter_mut() {
+            layer.map.init(vram, (0, 0).into(), between_updates);
+        }
+    }
+
+    pub fn commit(&mut self, vram: &mut VRamManager) {
+        for layer in self.layers.iter_mut() {
+            layer.map.commit(vram);
+        }
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        for layer in self.layers.iter_mut() {
+            layer.map.set_visible(visible);
+        }
+    }
+
+    /// Scroll every layer by its own parallax factor
+    pub fn set_scroll(
+        &mut self,
+        vram: &mut VRamManager,
+        scroll_pos: Vector2D<i32>,
+        shake: Vector2D<i32>,
+    ) {
+        for layer in self.layers.iter_mut() {
+            layer.set_scroll(vram, scroll_pos, shake);
+        }
+    }
+
+    /// Copy the current frame of every animated tile over its graphics in VRAM.
+    /// This runs every frame rather than only when the frame changes so that
+    /// tiles which have just scrolled in show the right frame straight away.
+    pub fn animate_tiles(&mut self, vram: &mut VRamManager) {
+        self.frame = self.frame.wrapping_add(1);
+
+        for animation in self.graphics.animations {
+            let tileset = &self.graphics.tilesets[animation.tileset as usize].tiles;
+            vram.replace_tile(
+                tileset,
+                animation.tile,
+                tileset,
+                animation.tile_at(self.frame),
+            );
+        }
+    }
+
+    pub fn clear(&mut self, vram: &mut VRamManager) {
+        for layer in self.layers.iter_mut() {
+            layer.map.clear(vram);
+        }
+    }
+}
+
+fn tile_layer<'a>(
     tiled: &'a Tiled0,
-) -> (InfiniteScrolledMap<'a>, InfiniteScrolledMap<'a>) {
-    let level_layers = tilemaps::LEVEL_LAYER_TILESETTINGS[level_number];
-    let level_tileset = &backgrounds::level.tiles;
+    level: &'a Level,
+    graphics: &'static LevelGraphics,
+    layer: &'static TileLayer,
+) -> ParallaxLayer<'a> {
+    let width = level.width as i32;
+    let height = level.height as i32;
 
-    let bg2_layer = level_layers[0];
-    let bg2 = InfiniteScrolledMap::new(
+    let map = InfiniteScrolledMap::new(
         tiled.background(
-            Priority::P2,
+            layer.priority,
             RegularBackgroundSize::Background32x32,
             TileFormat::FourBpp,
         ),
-        Box::new(|pos| {
-            let index = (pos.x + level.width as i32 * pos.y) as usize;
-            if index < bg2_layer.len() {
-                (level_tileset, bg2_layer[index])
-            } else {
-                (level_tileset, bg2_layer[0])
+        Box::new(move |pos| {
+            let x = layer.edge_x.resolve(pos.x, width);
+            let y = layer.edge_y.resolve(pos.y, height);
+            match x.zip(y) {
+                Some((x, y)) => {
+                    let (tileset, setting) = layer.cells.tile(graphics, x, y);
+                    (&tileset.tiles, setting)
+                }
+                None => (&graphics.tilesets[0].tiles, TileSetting::BLANK),
             }
         }),
     );
 
-    let bg3_layer = level_layers[1];
-    let bg3 = InfiniteScrolledMap::new(
-        tiled.background(
-            Priority::P3,
-            RegularBackgroundSize::Background32x32,
-            TileFormat::FourBpp,
-        ),
-        Box::new(|pos| {
-            let index = (pos.x + level.width as i32 * pos.y) as usize;
-            if index < bg3_layer.len() {
-                (level_tileset, bg3_layer[index])
-            } else {
-                (level_tileset, bg3_layer[0])
-            }
-        }),
-    );
+    ParallaxLayer { map, layer }
+}
+
+/// Sprites are drawn at this priority. Layers at P0 are in front of them.
+pub const SPRITE_PRIORITY: Priority = Priority::P1;
+
+/// Set up the level's tile layers and load its palettes. Tiles are only
+/// copied to VRAM as they scroll into view.
+pub fn load_backgrounds<'a>(
+    level_number: usize,
+    level: &'a Level,
+    tiled: &'a Tiled0,
+    vram: &mut VRamManager,
+) -> LevelBackgrounds<'a> {
+    load_palettes(vram, level_number);
 
-    (bg2, bg3)
+    let graphics = &tilemaps::LEVEL_GRAPHICS[level_number];
+    LevelBackgrounds {
+        layers: graphics
+            .layers
+            .iter()
+            .map(|layer| tile_layer(tiled, level, graphics, layer))
+            .collect(),
+        graphics,
+        frame: 0,
+    }
 }
@@ -1,9 +1,10 @@
 use crate::close_to_zero::CloseToZero;
+use crate::contact::{layer, Contact, ContactPhase};
 use crate::Level;
 use crate::{
     actor::{Action, Actor, ActorState},
-    game::ActorKey,
-    sfx::Sfx,
+    game::{ActorKey, GameEvent},
+    sfx::{Sfx, Sound},
     util,
 };
 use agb::mgba::DebugLevel;
@@ -12,6 +13,7 @@ use agb::{
     input::{Button, ButtonController, Tri},
     mgba::Mgba,
 };
+use alloc::vec::Vec;
 use slotmap::SlotMap;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
@@ -25,15 +27,13 @@ impl Behavior {
     pub fn update(
         &self,
         current_key: ActorKey,
-        player_key: ActorKey,
-        enemies_keys: &[ActorKey],
         actors: &mut SlotMap<ActorKey, Actor>,
         input: &ButtonController,
         level: &Level,
         sfx: &mut Sfx,
+        events: &mut Vec<GameEvent>,
     ) {
         let mut logger = Mgba::new();
-        if input.is_just_pressed(Button::A) {}
 
         match self {
             Self::Input => {
@@ -45,21 +45,27 @@ impl Behavior {
                     if actor.state == ActorState::Jumping && input.is_just_released(Button::B) {
                         actor.current_action = Action::JumpCut;
                     }
+                    if input.is_just_pressed(Button::A) {
+                        actor.current_action = Action::Attack;
+                    }
                 }
             }
             Self::Flap => {
                 if let Some(actor) = actors.get_mut(current_key) {
+                    // `Game::on_beat` sets the action, to flap up on one beat and
+                    // glide back down on the next
                     if actor.current_action == Action::Jump {
-                        actor.state = ActorState::Jumping;
-                        actor.velocity.y -= actor.max_velocity.y;
-                        sfx.bat_flap();
+                        actor.velocity.y = -actor.max_velocity.y;
+                        sfx.play_at(
+                            Sound::BatFlap,
+                            actor.collision_mask.position + actor.collision_mask.size / 2,
+                        );
                     }
-
-                    //actor.velocity.y += actor.acceleration.y;
-                    if actor.collision_mask.position.y > num!(3.0) {
-                        actor.current_action = Action::Jump;
+                    if actor.current_action == Action::JumpCut {
+                        actor.velocity.y = actor.max_velocity.y;
                     }
 
+                    //actor.velocity.y += actor.acceleration.y;
                     actor.current_action = Action::None;
                 }
             }
@@ -97,6 +103,9 @@ impl Behavior {
                     }
 
                     if actor.hit_ground(&level.get_solid_collision_rects(), num!(0.8)) {
+                        if actor.velocity.y > 0.into() {
+                            events.push(GameEvent::Landed(current_key, actor.velocity.y));
+                        }
                         actor.velocity.y = 0.into();
                         if actor.state == ActorState::Jumping {
                             actor.state = ActorState::Idle;
@@ -108,13 +117,20 @@ impl Behavior {
                     if actor.current_action == Action::Jump && actor.velocity.y == 0.into() {
                         actor.state = ActorState::Jumping;
                         actor.velocity.y -= actor.max_velocity.y;
-                        sfx.jump();
+                        sfx.play_at(
+                            Sound::Jump,
+                            actor.collision_mask.position + actor.collision_mask.size / 2,
+                        );
                     }
 
                     if actor.current_action == Action::JumpCut && actor.velocity.y != 0.into() {
                         actor.velocity.y = 0.into();
                     }
 
+                    if actor.current_action == Action::Attack {
+                        actor.attack();
+                    }
+
                     if actor.hit_ceiling(&level.get_solid_collision_rects(), num!(0.8)) {
                         actor.velocity.y = 0.into();
                     }
@@ -127,23 +143,6 @@ impl Behavior {
                     actor.current_action = Action::None;
                 }
 
-                if enemies_keys.iter().any(|enemy_key: &ActorKey| {
-                    let enemy = actors.get(*enemy_key);
-                    let current = actors.get(current_key);
-
-                    enemy
-                        .map(|e| {
-                            current
-                                .map(|c| e.collision_mask.touches(c.collision_mask))
-                                .is_some_and(|r| r)
-                        })
-                        .is_some_and(|r| r)
-                }) {
-                    if let Some(actor) = actors.get_mut(current_key) {
-                        actor.take_damage();
-                    }
-                }
-
                 if let Some(actor) = actors.get_mut(current_key) {
                     logger.as_mut().and_then(|l| {
                         l.print(
@@ -180,4 +179,52 @@ impl Behavior {
         //    });
         //}
     }
+
+    pub fn on_contact(
+        &self,
+        current_key: ActorKey,
+        contact: &Contact,
+        actors: &mut SlotMap<ActorKey, Actor>,
+        events: &mut Vec<GameEvent>,
+    ) {
+        match self {
+            Self::Player => {
+                let other_layer = contact.other_type.contact_filter().layer;
+                if contact.phase == ContactPhase::Begin && other_layer & layer::TRIGGER != 0 {
+                    events.push(GameEvent::Triggered(contact.other));
+                    return;
+                }
+                if contact.phase == ContactPhase::Begin && other_layer & layer::PICKUP != 0 {
+                    events.push(GameEvent::Collected(contact.other));
+                    return;
+                }
+                if contact.phase == ContactPhase::End || other_layer & layer::ENEMY == 0 {
+                    return;
+                }
+
+                let (Some(current), Some(other)) =
+                    (actors.get(current_key), actors.get(contact.other))
+                else {
+                    return;
+                };
+                let (hurt, hit) = (other.hits(current), current.hits(other));
+
+                if hurt {
+                    if let Some(actor) = actors.get_mut(current_key) {
+                        if actor.take_damage() {
+                            events.push(GameEvent::Hurt(current_key));
+                        }
+                    }
+                }
+                if hit {
+                    if let Some(enemy) = actors.get_mut(contact.other) {
+                        if enemy.take_damage() {
+                            events.push(GameEvent::Hit(contact.other));
+                        }
+                    }
+                }
+            }
+            Self::Input | Self::Flap => {}
+        }
+    }
 }
@@ -0,0 +1,160 @@
+use crate::util::lerp;
+use agb::display::{HEIGHT, WIDTH};
+use agb::fixnum::{num, FixedNum, Rect, Vector2D};
+use agb::input::Tri;
+use agb::rng;
+
+type Number = FixedNum<8>;
+
+pub struct Camera<'a> {
+    /// World position of the top left of the screen
+    pub position: Vector2D<Number>,
+    /// Screen area the target can move around in before the camera follows.
+    /// While the target is on the ground the camera snaps so that it stands on
+    /// the bottom edge of this area.
+    pub dead_zone: Rect<i32>,
+    /// How far ahead of the target the camera looks in the direction it faces
+    pub look_ahead: Number,
+    /// Fraction of the remaining distance the camera moves each frame
+    pub follow_speed: Number,
+    look_ahead_offset: Number,
+    shake_amplitude: Number,
+    shake_decay: Number,
+    shake_offset: Vector2D<i32>,
+    bounds: Rect<i32>,
+    locks: &'a [Rect<i32>],
+}
+
+impl<'a> Camera<'a> {
+    pub fn new(bounds: Rect<i32>, locks: &'a [Rect<i32>]) -> Self {
+        Self {
+            position: (0, 0).into(),
+            dead_zone: Rect {
+                position: (100, 56).into(),
+                size: (40, 56).into(),
+            },
+            look_ahead: 24.into(),
+            follow_speed: num!(0.1),
+            look_ahead_offset: 0.into(),
+            shake_amplitude: 0.into(),
+            shake_decay: 0.into(),
+            shake_offset: (0, 0).into(),
+            bounds,
+            locks,
+        }
+    }
+
+    /// The scroll offset to apply to backgrounds and sprites
+    pub fn scroll_pos(&self) -> Vector2D<i32> {
+        -self.position.floor()
+    }
+
+    /// The part of the world currently on screen
+    pub fn view(&self) -> Rect<i32> {
+        Rect {
+            position: self.position.floor(),
+            size: (WIDTH, HEIGHT).into(),
+        }
+    }
+
+    /// Jump straight to the target without easing, for the start of a level
+    pub fn snap_to(&mut self, target: Rect<Number>, grounded: bool) {
+        self.look_ahead_offset = 0.into();
+        let desired = self.desired_position(target, grounded);
+        self.position = self.clamp(desired, target);
+    }
+
+    pub fn update(&mut self, target: Rect<Number>, facing: Tri, grounded: bool) {
+        let facing: Number = (facing as i32).into();
+        self.look_ahead_offset = lerp(
+            self.look_ahead_offset,
+            self.look_ahead * facing,
+            self.follow_speed / 2,
+        );
+
+        let desired = self.desired_position(target, grounded);
+        let position = Vector2D {
+            x: lerp(self.position.x, desired.x, self.follow_speed),
+            y: lerp(self.position.y, desired.y, self.follow_speed),
+        };
+
+        self.position = self.clamp(position, target);
+    }
+
+    /// Start shaking the screen by up to `amplitude` pixels, shrinking by a
+    /// factor of `decay` each frame. A weaker shake never cuts a stronger one short.
+    pub fn shake(&mut self, amplitude: Number, decay: Number) {
+        if amplitude >= self.shake_amplitude {
+            self.shake_amplitude = amplitude;
+            self.shake_decay = decay;
+        }
+    }
+
+    /// Pick this frame's shake offset. Runs even while gameplay is frozen.
+    pub fn update_shake(&mut self) {
+        if self.shake_amplitude < num!(0.5) {
+            self.shake_amplitude = 0.into();
+            self.shake_offset = (0, 0).into();
+            return;
+        }
+
+        let range = self.shake_amplitude.trunc() * 2 + 1;
+        let offset = |r: i32| r.rem_euclid(range) - self.shake_amplitude.trunc();
+        self.shake_offset = (offset(rng::gen()), offset(rng::gen())).into();
+        self.shake_amplitude *= self.shake_decay;
+    }
+
+    /// Extra offset to add on top of `scroll_pos` while the screen is shaking
+    pub fn shake_offset(&self) -> Vector2D<i32> {
+        self.shake_offset
+    }
+
+    fn desired_position(&self, target: Rect<Number>, grounded: bool) -> Vector2D<Number> {
+        let focus_x = target.position.x + target.size.x / 2 + self.look_ahead_offset;
+        let feet_y = target.position.y + target.size.y;
+
+        let zone_left = Number::from(self.dead_zone.position.x);
+        let zone_right = zone_left + self.dead_zone.size.x;
+        let zone_top = Number::from(self.dead_zone.position.y);
+        let zone_bottom = zone_top + self.dead_zone.size.y;
+
+        let mut desired = self.position;
+
+        if focus_x < self.position.x + zone_left {
+            desired.x = focus_x - zone_left;
+        } else if focus_x > self.position.x + zone_right {
+            desired.x = focus_x - zone_right;
+        }
+
+        if grounded || feet_y > self.position.y + zone_bottom {
+            desired.y = feet_y - zone_bottom;
+        } else if feet_y < self.position.y + zone_top {
+            desired.y = feet_y - zone_top;
+        }
+
+        desired
+    }
+
+    /// Keep the screen inside the level, or inside the lock region the target is in
+    fn clamp(&self, position: Vector2D<Number>, target: Rect<Number>) -> Vector2D<Number> {
+        let centre = (target.position + target.size / 2).floor();
+        let bounds = self
+            .locks
+            .iter()
+            .find(|lock| lock.contains_point(centre))
+            .copied()
+            .unwrap_or(self.bounds);
+
+        Vector2D {
+            x: clamp_axis(position.x, bounds.position.x, bounds.size.x, WIDTH),
+            y: clamp_axis(position.y, bounds.position.y, bounds.size.y, HEIGHT),
+        }
+    }
+}
+
+/// Clamp one axis so the screen stays inside `start..start + length`, pinning
+/// to the start when the area is smaller than the screen.
+fn clamp_axis(value: Number, start: i32, length: i32, screen: i32) -> Number {
+    let max = (start + length - screen).max(start);
+    value.max(start.into()).min(max.into())
+}
@@ -0,0 +1,158 @@
+use crate::actor::Actor;
+use crate::game::ActorKey;
+use crate::level::EntityType;
+use alloc::vec::Vec;
+use slotmap::{SecondaryMap, SlotMap};
+
+/// Contact layers an entity can occupy or listen to
+pub mod layer {
+    pub const PLAYER: u8 = 1 << 0;
+    pub const ENEMY: u8 = 1 << 1;
+    pub const PICKUP: u8 = 1 << 2;
+    pub const TRIGGER: u8 = 1 << 3;
+    pub const PROJECTILE: u8 = 1 << 4;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ContactPhase {
+    Begin,
+    Stay,
+    End,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Contact {
+    pub phase: ContactPhase,
+    pub other: ActorKey,
+    pub other_type: EntityType,
+}
+
+/// The layers an entity occupies and the layers it wants contact events for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContactFilter {
+    pub layer: u8,
+    pub mask: u8,
+}
+
+impl ContactFilter {
+    pub const fn new(layer: u8, mask: u8) -> Self {
+        Self { layer, mask }
+    }
+
+    pub fn listens_to(&self, other: &ContactFilter) -> bool {
+        self.mask & other.layer != 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Pair {
+    a: (ActorKey, EntityType),
+    b: (ActorKey, EntityType),
+}
+
+impl Pair {
+    fn new(a: (ActorKey, EntityType), b: (ActorKey, EntityType)) -> Self {
+        if a.0 < b.0 {
+            Self { a, b }
+        } else {
+            Self { a: b, b: a }
+        }
+    }
+
+    fn same_actors(&self, other: &Pair) -> bool {
+        self.a.0 == other.a.0 && self.b.0 == other.b.0
+    }
+}
+
+/// Tracks overlapping actor pairs between frames to produce begin/stay/end events
+#[derive(Default)]
+pub struct ContactTracker {
+    touching: Vec<Pair>,
+}
+
+impl ContactTracker {
+    pub fn new() -> Self {
+        Self {
+            touching: Vec::new(),
+        }
+    }
+
+    /// Find the overlapping actor pairs for this frame and return the contact
+    /// events to deliver, keyed by the actor receiving them.
+    pub fn update(
+        &mut self,
+        actors: &SlotMap<ActorKey, Actor>,
+        entity_types: &SecondaryMap<ActorKey, EntityType>,
+    ) -> Vec<(ActorKey, Contact)> {
+        let candidates: Vec<(ActorKey, EntityType, ContactFilter)> = actors
+            .keys()
+            .filter_map(|key| {
+                entity_types
+                    .get(key)
+                    .map(|entity_type| (key, *entity_type, entity_type.contact_filter()))
+            })
+            .collect();
+
+        let mut touching = Vec::with_capacity(self.touching.len());
+        for (i, (key_a, type_a, filter_a)) in candidates.iter().enumerate() {
+            for (key_b, type_b, filter_b) in candidates[i + 1..].iter() {
+                if !filter_a.listens_to(filter_b) && !filter_b.listens_to(filter_a) {
+                    continue;
+                }
+
+                let (Some(actor_a), Some(actor_b)) = (actors.get(*key_a), actors.get(*key_b))
+                else {
+                    continue;
+                };
+
+                if actor_a.touches(actor_b) {
+                    touching.push(Pair::new((*key_a, *type_a), (*key_b, *type_b)));
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        for pair in touching.iter() {
+            let phase = if self.touching.iter().any(|p| p.same_actors(pair)) {
+                ContactPhase::Stay
+            } else {
+                ContactPhase::Begin
+            };
+            push_events(&mut events, pair, phase);
+        }
+        for pair in self.touching.iter() {
+            if !touching.iter().any(|p| p.same_actors(pair)) {
+                push_events(&mut events, pair, ContactPhase::End);
+            }
+        }
+
+        self.touching = touching;
+        events
+    }
+}
+
+fn push_events(events: &mut Vec<(ActorKey, Contact)>, pair: &Pair, phase: ContactPhase) {
+    let (key_a, type_a) = pair.a;
+    let (key_b, type_b) = pair.b;
+
+    if type_a.contact_filter().listens_to(&type_b.contact_filter()) {
+        events.push((
+            key_a,
+            Contact {
+                phase,
+                other: key_b,
+                other_type: type_b,
+            },
+        ));
+    }
+    if type_b.contact_filter().listens_to(&type_a.contact_filter()) {
+        events.push((
+            key_b,
+            Contact {
+                phase,
+                other: key_a,
+                other_type: type_a,
+            },
+        ));
+    }
+}
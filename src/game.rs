@@ -1,40 +1,147 @@
 use crate::behaviors::Behavior;
+use crate::camera::Camera;
+use crate::contact::ContactTracker;
 use crate::level::EntityType;
-use crate::sfx::Sfx;
-use crate::util::lerp;
+use crate::sfx::{MusicEvent, Sfx, Sound};
 use agb::display::object::OamIterator;
 use agb::display::object::ObjectUnmanaged;
 use agb::display::object::SpriteLoader;
 use agb::display::HEIGHT;
 use agb::display::WIDTH;
 use agb::fixnum::num;
+use agb::fixnum::FixedNum;
 use agb::fixnum::Num;
 use agb::fixnum::Rect;
 use agb::fixnum::Vector2D;
 use agb::input::ButtonController;
 use agb::input::Tri;
-use alloc::vec;
 use alloc::vec::Vec;
 use slotmap::new_key_type;
 use slotmap::Key;
 use slotmap::SecondaryMap;
 use slotmap::SlotMap;
 
+use crate::actor::Action;
 use crate::actor::Actor;
+use crate::actor::ActorState;
+use crate::backgrounds::SPRITE_PRIORITY;
 use crate::level::Entity;
 use crate::level::Level;
+use crate::level::Respawn;
+use crate::resources;
 
 new_key_type! { pub struct ActorKey; }
 
+type Number = FixedNum<8>;
+
+/// Number of hardware object slots
+const OAM_SLOTS: usize = 128;
+
+/// Actors further than this many pixels outside the screen stop simulating
+const ACTIVATION_RADIUS: i32 = 64;
+
+/// Falling faster than this when landing shakes the screen
+const HEAVY_LANDING_SPEED: i32 = 4;
+
+/// The player's health at the start of a level, and the most potions can heal
+/// them to
+pub const PLAYER_HEALTH: i32 = 3;
+
+/// Things that happened during a behavior update that the game reacts to as a
+/// whole, such as shaking the screen
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+    /// The actor took damage
+    Hurt(ActorKey),
+    /// The actor was damaged by the player
+    Hit(ActorKey),
+    /// The actor landed on the ground while falling at this speed
+    Landed(ActorKey, Number),
+    /// The player touched a trigger, such as a checkpoint
+    Triggered(ActorKey),
+    /// The player touched a pickup
+    Collected(ActorKey),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LevelStatus {
+    Playing,
+    /// The player reached the door, taking this many frames
+    Complete(u32),
+    Dead,
+    /// The player quit from the pause menu
+    Quit,
+    /// The player walked off the edge into a neighbouring level
+    Travel(RoomEntry),
+}
+
+/// The player's state as they move into a neighbouring level, with their
+/// position given in that level's coordinates
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RoomEntry {
+    pub level: usize,
+    pub position: Vector2D<Number>,
+    pub velocity: Vector2D<Number>,
+    pub facing: Tri,
+    pub state: ActorState,
+    pub health: Number,
+    /// How long the player has been in the level so far
+    pub frames: u32,
+}
+
+/// The checkpoint the player last touched, and which of the level's entities
+/// had been defeated by then. Entities that never respawn are added when they
+/// are defeated later.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    /// Index of the checkpoint in the level's entities
+    pub spawn: usize,
+    /// A bit for each entity in the level. build.rs makes sure there are at
+    /// most 64.
+    pub defeated: u64,
+}
+
+/// Where the player starts when a level is loaded
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LevelStart {
+    /// The level's `PLAYER` entity
+    Spawn,
+    /// Carried over from a neighbouring level
+    Room(RoomEntry),
+    /// A checkpoint touched earlier
+    Checkpoint(Checkpoint),
+}
+
 pub struct Game<'a> {
     level: &'a Level,
     input: ButtonController,
     actors: SlotMap<ActorKey, Actor<'a>>,
     behaviors: SecondaryMap<ActorKey, &'a [Behavior]>,
+    entity_types: SecondaryMap<ActorKey, EntityType>,
+    /// Index of each actor in the level's entities
+    spawns: SecondaryMap<ActorKey, usize>,
+    /// Entities that have been defeated, by index
+    defeated: u64,
+    /// Pickups that have been collected, by `Level::pickup_index`
+    collected: u32,
+    checkpoint: Option<Checkpoint>,
+    contacts: ContactTracker,
     player: ActorKey,
-    enemies: Vec<ActorKey>,
+    status: LevelStatus,
+    camera: Camera<'a>,
     frame: usize,
+    /// Actors further than this many pixels outside the screen are frozen
+    /// until the camera comes near them again
+    pub activation_radius: i32,
+    hit_stop: u16,
+    events: Vec<GameEvent>,
     render_cache: Vec<RenderCache>,
+    render_frame: usize,
+    /// How many object slots actors may use each frame
+    pub sprite_budget: usize,
+    /// Cycle which sprites are hidden when there are more than `sprite_budget`,
+    /// rather than always dropping the same ones
+    pub flicker: bool,
     pub scroll_pos: Vector2D<i32>,
 }
 
@@ -45,30 +152,43 @@ impl<'a> Game<'a> {
             input: ButtonController::new(),
             actors: SlotMap::with_capacity_and_key(100),
             behaviors: SecondaryMap::with_capacity(100),
+            entity_types: SecondaryMap::with_capacity(100),
+            spawns: SecondaryMap::with_capacity(100),
+            defeated: 0,
+            collected: 0,
+            checkpoint: None,
+            contacts: ContactTracker::new(),
             player: ActorKey::null(),
-            enemies: vec![ActorKey::null(); 100],
+            status: LevelStatus::Playing,
+            camera: Camera::new(level.bounds(), level.camera_locks),
             frame: 0,
+            activation_radius: ACTIVATION_RADIUS,
+            hit_stop: 0,
+            events: Vec::new(),
             render_cache: Vec::with_capacity(100),
+            render_frame: 0,
+            sprite_budget: OAM_SLOTS,
+            flicker: true,
             scroll_pos: (0, 0).into(),
         }
     }
 
     pub fn load_level_assets(&mut self) {
-        for Entity(entity, position, maybe_size, behaviors, sprite_offset) in
-            self.level.starting_positions
+        for (spawn, Entity(entity, position, maybe_size, behaviors, _, _)) in
+            self.level.starting_positions.iter().enumerate()
         {
             let position = *position;
             let maybe_size = *maybe_size;
-            let offset = *sprite_offset;
             let key = match entity {
                 EntityType::Player => {
                     let actor = Actor::new(
                         entity.tags(),
+                        entity.boxes(),
                         position.into(),
                         maybe_size.map(|size| size.into()),
-                        offset.into(),
                         Some((num!(1.4), num!(7.0)).into()),
                         Some((num!(0.6), num!(0.4)).into()),
+                        PLAYER_HEALTH.into(),
                     );
                     let key = self.actors.insert(actor);
                     self.player = key;
@@ -77,38 +197,100 @@ impl<'a> Game<'a> {
                 EntityType::Bat => {
                     let actor = Actor::new(
                         entity.tags(),
+                        entity.boxes(),
                         position.into(),
                         maybe_size.map(|size| size.into()),
-                        offset.into(),
                         Some((num!(1.4), num!(0.06)).into()),
                         Some((num!(0.6), num!(0.008)).into()),
+                        1.into(),
                     );
-                    let key = self.actors.insert(actor);
-                    self.enemies.push(key);
-                    key
+                    self.actors.insert(actor)
+                }
+                EntityType::Checkpoint
+                | EntityType::Coin
+                | EntityType::Potion
+                | EntityType::Key => {
+                    let actor = Actor::new(
+                        entity.tags(),
+                        entity.boxes(),
+                        position.into(),
+                        maybe_size.map(|size| size.into()),
+                        Some((0, 0).into()),
+                        Some((0, 0).into()),
+                        1.into(),
+                    );
+                    self.actors.insert(actor)
                 }
             };
 
             self.behaviors.insert(key, *behaviors);
+            self.entity_types.insert(key, *entity);
+            self.spawns.insert(key, spawn);
         }
+
+        if let Some(player) = self.actors.get(self.player) {
+            self.camera.snap_to(player.collision_mask, true);
+            self.scroll_pos = self.camera.scroll_pos();
+        }
+    }
+
+    /// Carry the player over from the level they just left
+    pub fn enter_room(&mut self, entry: &RoomEntry) {
+        let Some(player) = self.actors.get_mut(self.player) else {
+            return;
+        };
+
+        player.collision_mask.position = entry.position;
+        player.velocity = entry.velocity;
+        player.facing = entry.facing;
+        player.state = entry.state;
+        player.set_health(entry.health);
+        self.frame = entry.frames as usize;
+
+        self.camera
+            .snap_to(player.collision_mask, entry.velocity.y == 0.into());
+        self.scroll_pos = self.camera.scroll_pos();
     }
 
     pub fn update(&mut self, sfx: &mut Sfx) {
         self.input.update();
+        self.camera.update_shake();
+
+        if self.hit_stop > 0 {
+            self.hit_stop -= 1;
+            return;
+        }
+
         self.frame = self.frame.wrapping_add(1);
 
-        let actor_keys: Vec<ActorKey> = self.actors.keys().collect();
+        let view = self.camera.view();
+        sfx.set_listener(view.position + view.size / 2);
+
+        let actor_keys = self.active_actors();
+        for event in sfx.music_events() {
+            match event {
+                MusicEvent::Beat(beat) => self.on_beat(&actor_keys, *beat),
+            }
+        }
+
+        for &actor_key in actor_keys.iter() {
+            if let Some(actor) = self.actors.get_mut(actor_key) {
+                actor.animation_frame = self.frame / 10;
+                actor.invulnerable_frames = actor.invulnerable_frames.saturating_sub(1);
+                actor.attack_frames = actor.attack_frames.saturating_sub(1);
+            }
+        }
+
         for actor_key in actor_keys {
             if let Some(behaviors_for_actor) = self.behaviors.get(actor_key) {
                 for behavior in behaviors_for_actor.iter() {
                     behavior.update(
                         actor_key,
-                        self.player,
-                        &*self.enemies,
                         &mut self.actors,
                         &self.input,
-                        &self.level,
+                        self.level,
                         sfx,
+                        &mut self.events,
                     );
                 }
             }
@@ -118,91 +300,462 @@ impl<'a> Game<'a> {
             }
         }
 
+        for (actor_key, contact) in self.contacts.update(&self.actors, &self.entity_types) {
+            if let Some(behaviors_for_actor) = self.behaviors.get(actor_key) {
+                for behavior in behaviors_for_actor.iter() {
+                    behavior.on_contact(actor_key, &contact, &mut self.actors, &mut self.events);
+                }
+            }
+        }
+
+        self.handle_events(sfx);
+        self.despawn_dead_actors();
+        self.status = self.check_status();
+
         if let Some(player) = self.actors.get(self.player) {
-            let Rect { position, size: _ } = player.collision_mask;
-
-            let bound_x = Num::from(WIDTH / 2);
-            let bound_y = Num::from(HEIGHT / 2);
-
-            self.scroll_pos = Vector2D {
-                x: Num::min(
-                    bound_x,
-                    lerp(
-                        self.scroll_pos.x.into(),
-                        (bound_x - position.x).into(),
-                        num!(0.05),
-                    ),
-                )
-                .trunc(),
-                y: Num::min(
-                    bound_y,
-                    lerp(
-                        self.scroll_pos.y.into(),
-                        (bound_y - position.y).into(),
-                        num!(0.05),
-                    ),
-                )
-                .trunc(),
+            self.camera.update(
+                player.collision_mask,
+                player.facing,
+                player.velocity.y == 0.into(),
+            );
+            self.scroll_pos = self.camera.scroll_pos();
+        }
+    }
+
+    pub fn status(&self) -> LevelStatus {
+        self.status
+    }
+
+    /// Freeze gameplay for a number of frames, while still rendering
+    pub fn hit_stop(&mut self, frames: u16) {
+        self.hit_stop = self.hit_stop.max(frames);
+    }
+
+    /// Offset to add to everything drawn on screen this frame
+    pub fn screen_shake(&self) -> Vector2D<i32> {
+        self.camera.shake_offset()
+    }
+
+    /// Bats flap in time with the music, up on even beats and down on odd ones
+    fn on_beat(&mut self, actor_keys: &[ActorKey], beat: u32) {
+        let action = if beat % 2 == 0 {
+            Action::Jump
+        } else {
+            Action::JumpCut
+        };
+
+        for &actor_key in actor_keys {
+            let flaps = self
+                .behaviors
+                .get(actor_key)
+                .is_some_and(|behaviors| behaviors.contains(&Behavior::Flap));
+            if let (true, Some(actor)) = (flaps, self.actors.get_mut(actor_key)) {
+                actor.current_action = action;
+            }
+        }
+    }
+
+    fn handle_events(&mut self, sfx: &mut Sfx) {
+        for event in core::mem::take(&mut self.events) {
+            match event {
+                GameEvent::Hurt(key) if key == self.player => {
+                    self.camera.shake(num!(4.0), num!(0.85));
+                    self.hit_stop(8);
+                }
+                GameEvent::Hurt(_) => {}
+                GameEvent::Hit(_) => {
+                    self.camera.shake(num!(2.0), num!(0.8));
+                    self.hit_stop(4);
+                }
+                GameEvent::Landed(key, speed) => {
+                    if key == self.player && speed > HEAVY_LANDING_SPEED.into() {
+                        self.camera
+                            .shake((speed - HEAVY_LANDING_SPEED) / 2, num!(0.7));
+                    }
+                }
+                GameEvent::Triggered(key) => {
+                    if self.entity_types.get(key) == Some(&EntityType::Checkpoint) {
+                        self.activate_checkpoint(key, sfx);
+                    }
+                }
+                GameEvent::Collected(key) => self.collect(key, sfx),
+            }
+        }
+    }
+
+    /// Take a pickup out of the level, healing the player if it was a potion
+    fn collect(&mut self, key: ActorKey, sfx: &mut Sfx) {
+        let (Some(&entity_type), Some(&spawn)) = (self.entity_types.get(key), self.spawns.get(key))
+        else {
+            return;
+        };
+        let Some(index) = self.level.pickup_index(spawn) else {
+            return;
+        };
+
+        if entity_type == EntityType::Potion {
+            if let Some(player) = self.actors.get_mut(self.player) {
+                let health = (player.health() + 1).min(PLAYER_HEALTH.into());
+                player.set_health(health);
+            }
+        }
+
+        if let Some(actor) = self.actors.get(key) {
+            let mask = actor.collision_mask;
+            sfx.play_at(Sound::Pickup, mask.position + mask.size / 2);
+        }
+
+        self.collected |= 1 << index;
+        self.remove_actor(key);
+    }
+
+    /// The player's health, or 0 once they are gone
+    pub fn player_health(&self) -> Number {
+        self.actors.get(self.player).map_or(0.into(), Actor::health)
+    }
+
+    /// Pickups collected in this level so far, a bit for each by
+    /// `Level::pickup_index`
+    pub fn collected(&self) -> u32 {
+        self.collected
+    }
+
+    /// How many pickups of this type have been collected in this level
+    pub fn collected_count(&self, entity_type: EntityType) -> usize {
+        self.level
+            .starting_positions
+            .iter()
+            .enumerate()
+            .filter(|(spawn, entity)| {
+                entity.0 == entity_type
+                    && self
+                        .level
+                        .pickup_index(*spawn)
+                        .is_some_and(|index| self.collected & (1 << index) != 0)
+            })
+            .count()
+    }
+
+    /// Leave out the pickups collected on an earlier visit
+    pub fn remove_collected(&mut self, collected: u32) {
+        let taken: Vec<ActorKey> = self
+            .spawns
+            .iter()
+            .filter(|(_, &spawn)| {
+                self.level
+                    .pickup_index(spawn)
+                    .is_some_and(|index| collected & (1 << index) != 0)
+            })
+            .map(|(key, _)| key)
+            .collect();
+        for key in taken {
+            self.remove_actor(key);
+        }
+        self.collected |= collected;
+    }
+
+    /// Remember the checkpoint to respawn at, lighting it and putting out the
+    /// previous one
+    fn activate_checkpoint(&mut self, key: ActorKey, sfx: &mut Sfx) {
+        let Some(&spawn) = self.spawns.get(key) else {
+            return;
+        };
+        if self
+            .checkpoint
+            .is_some_and(|checkpoint| checkpoint.spawn == spawn)
+        {
+            return;
+        }
+
+        if let Some(previous) = self
+            .checkpoint
+            .and_then(|checkpoint| self.spawned(checkpoint.spawn))
+        {
+            self.set_checkpoint_lit(previous, false);
+        }
+        self.set_checkpoint_lit(key, true);
+        self.checkpoint = Some(Checkpoint {
+            spawn,
+            defeated: self.defeated,
+        });
+
+        if let Some(actor) = self.actors.get(key) {
+            let mask = actor.collision_mask;
+            sfx.play_at(Sound::Checkpoint, mask.position + mask.size / 2);
+        }
+    }
+
+    fn set_checkpoint_lit(&mut self, key: ActorKey, lit: bool) {
+        if let Some(actor) = self.actors.get_mut(key) {
+            let tag = if lit {
+                resources::CHECKPOINT_LIT
+            } else {
+                resources::CHECKPOINT
             };
+            actor.tags.insert(ActorState::Idle, tag);
         }
+    }
 
-        //self.cache_render(sprite_loader);
+    /// The actor spawned from the level's entity at this index, if it is
+    /// still around
+    fn spawned(&self, spawn: usize) -> Option<ActorKey> {
+        self.spawns
+            .iter()
+            .find(|(_, &other)| other == spawn)
+            .map(|(key, _)| key)
     }
 
-    //fn cache_render(&mut self, sprite_loader: &mut SpriteLoader) {
-    //    self.render_cache = self
-    //        .actors
-    //        .iter()
-    //        .map(|(_, actor)| {
-    //            let object = ObjectUnmanaged::new(
-    //                sprite_loader.get_vram_sprite(actor.tag.animation_sprite(self.frame / 16)),
-    //            );
-    //            RenderCache { object }
-    //        })
-    //        .collect();
-    //    self.render_cache
-    //        .sort_unstable_by_key(|r| r.sorting_number());
-    //}
+    /// The checkpoint the player last touched in this level
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        self.checkpoint
+    }
 
-    pub fn render(&mut self, loader: &mut SpriteLoader, oam: &mut OamIterator) {
-        //for item in self.render_cache.iter() {
-        //    item.render(oam);
-        //}
-
-        for (_, actor) in self.actors.iter() {
-            if let Some(tag) = actor.tags.get(&actor.state) {
-                let sprite = loader.get_vram_sprite(tag.animation_sprite(self.frame / 10));
-                let mut obj = ObjectUnmanaged::new(sprite);
-                let position =
-                    actor.collision_mask.position + self.scroll_pos.into() + actor.sprite_offset;
-                obj.show()
-                    .set_position(Vector2D {
-                        x: position.x.trunc(),
-                        y: position.y.trunc(),
+    /// Start from a checkpoint, with the entities that don't respawn left
+    /// defeated
+    pub fn respawn_at(&mut self, checkpoint: &Checkpoint) {
+        let stay_defeated: Vec<ActorKey> = self
+            .spawns
+            .iter()
+            .filter(|(_, &spawn)| {
+                checkpoint.defeated & (1 << spawn) != 0
+                    && self.level.starting_positions[spawn].4 == Respawn::Never
+            })
+            .map(|(key, _)| key)
+            .collect();
+        for key in stay_defeated {
+            self.remove_actor(key);
+        }
+        self.defeated = checkpoint.defeated;
+
+        let Some(key) = self.spawned(checkpoint.spawn) else {
+            return;
+        };
+        self.set_checkpoint_lit(key, true);
+        self.checkpoint = Some(*checkpoint);
+
+        let Some(target) = self.actors.get(key).map(|actor| actor.collision_mask) else {
+            return;
+        };
+        let Some(player) = self.actors.get_mut(self.player) else {
+            return;
+        };
+
+        // Stand the player on the base of the checkpoint
+        let size = player.collision_mask.size;
+        player.collision_mask.position =
+            target.position + target.size - Vector2D::new(target.size.x / 2 + size.x / 2, size.y);
+
+        self.camera.snap_to(player.collision_mask, true);
+        self.scroll_pos = self.camera.scroll_pos();
+    }
+
+    /// Actors near enough to the camera to simulate this frame, along with any
+    /// that always simulate
+    fn active_actors(&self) -> Vec<ActorKey> {
+        let view = self.camera.view();
+        let area = Rect {
+            position: view.position - (self.activation_radius, self.activation_radius).into(),
+            size: view.size + (self.activation_radius * 2, self.activation_radius * 2).into(),
+        };
+
+        self.actors
+            .iter()
+            .filter(|(key, actor)| {
+                self.always_active(*key)
+                    || actor.collision_mask.touches(Rect {
+                        position: area.position.into(),
+                        size: area.size.into(),
                     })
-                    .set_hflip(actor.facing == Tri::Negative);
-                if let Some(slot) = oam.next() {
-                    slot.set(&obj);
+            })
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Whether the actor simulates however far it is from the camera, because
+    /// of its type or because its entity was marked `always_active`
+    fn always_active(&self, key: ActorKey) -> bool {
+        self.entity_types
+            .get(key)
+            .is_some_and(EntityType::always_active)
+            || self
+                .spawns
+                .get(key)
+                .is_some_and(|&spawn| self.level.starting_positions[spawn].5)
+    }
+
+    fn despawn_dead_actors(&mut self) {
+        let dead: Vec<ActorKey> = self
+            .actors
+            .iter()
+            .filter(|(key, actor)| *key != self.player && actor.is_dead())
+            .map(|(key, _)| key)
+            .collect();
+
+        for key in dead {
+            if let Some(&spawn) = self.spawns.get(key) {
+                self.defeated |= 1 << spawn;
+                // So that it is still gone after respawning at the checkpoint
+                // touched before it was defeated
+                if let Some(checkpoint) = self.checkpoint.as_mut() {
+                    if self.level.starting_positions[spawn].4 == Respawn::Never {
+                        checkpoint.defeated |= 1 << spawn;
+                    }
                 }
             }
+            self.remove_actor(key);
+        }
+    }
+
+    fn remove_actor(&mut self, key: ActorKey) {
+        self.actors.remove(key);
+        self.behaviors.remove(key);
+        self.entity_types.remove(key);
+        self.spawns.remove(key);
+    }
+
+    fn check_status(&self) -> LevelStatus {
+        let Some(player) = self.actors.get(self.player) else {
+            return LevelStatus::Dead;
+        };
+
+        let bounds = self.level.bounds();
+        let centre = (player.collision_mask.position + player.collision_mask.size / 2).floor();
+        if !bounds.contains_point(centre) {
+            if let Some(neighbour) = self.level.neighbour_at(centre) {
+                return LevelStatus::Travel(RoomEntry {
+                    level: neighbour.level,
+                    position: player.collision_mask.position - neighbour.area.position.into(),
+                    velocity: player.velocity,
+                    facing: player.facing,
+                    state: player.state,
+                    health: player.health(),
+                    frames: self.frame as u32,
+                });
+            }
+        }
+
+        let level_bottom = Num::from(bounds.position.y + bounds.size.y);
+        if player.is_dead() || player.collision_mask.position.y > level_bottom {
+            return LevelStatus::Dead;
+        }
+
+        let reached_door = self.level.get_door_collision_rects().iter().any(|door| {
+            player.collision_mask.touches(Rect {
+                position: door.position.into(),
+                size: door.size.into(),
+            })
+        });
+        if reached_door {
+            return LevelStatus::Complete(self.frame as u32);
+        }
+
+        LevelStatus::Playing
+    }
+
+    /// Build the render queue for this frame, sorted so that the lowest z
+    /// layer is drawn on top
+    fn cache_render(&mut self, loader: &mut SpriteLoader) {
+        let offset = self.scroll_pos + self.screen_shake();
+        let screen = Rect {
+            position: (0, 0).into(),
+            size: (WIDTH, HEIGHT).into(),
+        };
+        self.render_cache.clear();
+        for (key, actor) in self.actors.iter() {
+            let Some(tag) = actor.tags.get(&actor.shown_state()) else {
+                continue;
+            };
+            let sprite = tag.animation_sprite(actor.animation_frame);
+            let position = actor.sprite_position() + offset.into();
+            let (width, height) = sprite.size().to_width_height();
+            let on_screen = screen.touches(Rect {
+                position: position.floor(),
+                size: (width as i32, height as i32).into(),
+            });
+            if !on_screen {
+                continue;
+            }
+
+            let mut object = ObjectUnmanaged::new(loader.get_vram_sprite(sprite));
+            object
+                .show()
+                .set_position(Vector2D {
+                    x: position.x.trunc(),
+                    y: position.y.trunc(),
+                })
+                .set_hflip(actor.is_flipped())
+                .set_priority(SPRITE_PRIORITY);
+
+            let z = self
+                .entity_types
+                .get(key)
+                .map_or(i32::MAX, EntityType::z_layer);
+            self.render_cache.push(RenderCache { object, z });
+        }
+        self.render_cache.sort_by_key(RenderCache::sorting_number);
+    }
+
+    pub fn render(&mut self, loader: &mut SpriteLoader, oam: &mut OamIterator) {
+        self.cache_render(loader);
+        self.render_frame = self.render_frame.wrapping_add(1);
+
+        let overflow = self.render_cache.len().saturating_sub(self.sprite_budget);
+        #[cfg(debug_assertions)]
+        if overflow > 0 {
+            agb::println!(
+                "OAM overflow: {} sprites queued, budget {}",
+                self.render_cache.len(),
+                self.sprite_budget
+            );
+        }
+
+        // The sprites at the front of the queue, which includes the player, are
+        // always drawn. When flickering, a different run of the rest is skipped
+        // each frame so that every one of them is shown some of the time instead
+        // of the same ones always being dropped off the end.
+        let always_drawn = self.sprite_budget.saturating_sub(overflow);
+        let competing = self.render_cache.len().saturating_sub(always_drawn);
+        let skip_from = if self.flicker && overflow > 0 {
+            (self.render_frame * overflow) % competing
+        } else {
+            competing - overflow
+        };
+        let skipped = |idx: usize| {
+            overflow > 0
+                && idx >= always_drawn
+                && (idx - always_drawn + competing - skip_from) % competing < overflow
+        };
+
+        for (idx, item) in self.render_cache.iter().enumerate() {
+            if skipped(idx) {
+                continue;
+            }
+            if !item.render(oam) {
+                #[cfg(debug_assertions)]
+                agb::println!("OAM overflow: ran out of object slots");
+                break;
+            }
         }
     }
 }
 
-// TODO refactor to use cached rendering
 struct RenderCache {
     object: ObjectUnmanaged,
+    z: i32,
 }
 
 impl RenderCache {
-    pub fn render(&self, oam: &mut OamIterator) {
+    /// Returns false if there were no object slots left
+    pub fn render(&self, oam: &mut OamIterator) -> bool {
         if let Some(slot) = oam.next() {
             slot.set(&self.object);
+            true
+        } else {
+            false
         }
     }
 
     pub fn sorting_number(&self) -> i32 {
-        // TODO return z index based on actor type
-        42
+        self.z
     }
 }
@@ -0,0 +1,125 @@
+use crate::game::{Game, PLAYER_HEALTH};
+use crate::level::{EntityType, Level};
+use crate::resources;
+use crate::scene;
+use agb::display::object::{OamIterator, ObjectTextRender, ObjectUnmanaged, SpriteLoader, Tag};
+use agb::display::{Priority, WIDTH};
+use agb::fixnum::Vector2D;
+use alloc::format;
+
+/// Object slots kept back from actors for the HUD, so a crowded screen never
+/// hides the player's health
+pub const HUD_SPRITES: usize = 24;
+
+/// How long the level's name is shown for when it starts, in frames
+const BANNER_FRAMES: u16 = 180;
+
+const BANNER_Y: i32 = 24;
+
+/// Distance between the hearts, which are narrower than their sprites
+const HEART_SPACING: i32 = 14;
+
+/// Space for the coin count to the right of its icon
+const COUNTER_WIDTH: i32 = 40;
+
+/// The most key icons shown, one for each key collected
+const MAX_KEY_ICONS: usize = 3;
+
+/// Hearts for the player's health, the coins collected in the level and, as
+/// the level starts, its name
+pub struct Hud {
+    banner: Option<ObjectTextRender<'static>>,
+    banner_frames: u16,
+    coins_total: usize,
+    /// The coin count `counter` was laid out for
+    coins: Option<usize>,
+    counter: Option<ObjectTextRender<'static>>,
+    frame: usize,
+}
+
+impl Hud {
+    pub fn new(level: &Level) -> Self {
+        Self {
+            banner: Some(scene::text_render(level.name, (WIDTH, 32))),
+            banner_frames: BANNER_FRAMES,
+            coins_total: level.pickup_count(EntityType::Coin),
+            coins: None,
+            counter: None,
+            frame: 0,
+        }
+    }
+
+    /// Type out the banner and keep the counter up to date. This isn't called
+    /// while paused, so the banner waits for the game to carry on.
+    pub fn update(&mut self, game: &Game) {
+        self.frame = self.frame.wrapping_add(1);
+
+        self.banner_frames = self.banner_frames.saturating_sub(1);
+        if self.banner_frames == 0 {
+            self.banner = None;
+        }
+        if let Some(banner) = self.banner.as_mut() {
+            banner.next_letter_group();
+            banner.update((0, BANNER_Y));
+        }
+
+        let coins = game.collected_count(EntityType::Coin);
+        if self.coins_total > 0 && self.coins != Some(coins) {
+            let mut counter = scene::text_render(
+                &format!("{coins}/{}", self.coins_total),
+                (COUNTER_WIDTH, 16),
+            );
+            counter.next_line();
+            counter.update((WIDTH - COUNTER_WIDTH, 2));
+            self.counter = Some(counter);
+            self.coins = Some(coins);
+        }
+    }
+
+    /// Draw the HUD before the actors so that it is in front of them
+    pub fn render(&mut self, game: &Game, loader: &mut SpriteLoader, oam: &mut OamIterator) {
+        let health = game.player_health();
+        for heart in 0..PLAYER_HEALTH {
+            let tag = if health > heart.into() {
+                resources::HEART
+            } else {
+                resources::HEART_EMPTY
+            };
+            show(tag, 0, (2 + heart * HEART_SPACING, 0).into(), loader, oam);
+        }
+
+        if let Some(counter) = self.counter.as_mut() {
+            let position = (WIDTH - COUNTER_WIDTH - 14, 0).into();
+            show(resources::COIN, self.frame / 10, position, loader, oam);
+            counter.commit(oam);
+        }
+
+        let keys = game.collected_count(EntityType::Key).min(MAX_KEY_ICONS);
+        for key in 0..keys as i32 {
+            let position = (WIDTH - 18 - key * 12, 16).into();
+            show(resources::KEY, 0, position, loader, oam);
+        }
+
+        if let Some(banner) = self.banner.as_mut() {
+            banner.commit(oam);
+        }
+    }
+}
+
+fn show(
+    tag: &'static Tag,
+    frame: usize,
+    position: Vector2D<i32>,
+    loader: &mut SpriteLoader,
+    oam: &mut OamIterator,
+) {
+    let mut object = ObjectUnmanaged::new(loader.get_vram_sprite(tag.animation_sprite(frame)));
+    object
+        .show()
+        .set_position(position)
+        .set_priority(Priority::P0);
+
+    if let Some(slot) = oam.next() {
+        slot.set(&object);
+    }
+}
@@ -1,3 +1,6 @@
+use crate::contact::{layer, ContactFilter};
+use crate::sfx::Music;
+use crate::sprite_boxes::{boxes, SpriteBoxes};
 use crate::{actor::ActorState, behaviors::Behavior, resources};
 use agb::{
     display::object::Tag,
@@ -10,6 +13,10 @@ use alloc::vec::Vec;
 pub enum EntityType {
     Player,
     Bat,
+    Checkpoint,
+    Coin,
+    Potion,
+    Key,
 }
 
 impl EntityType {
@@ -20,14 +27,103 @@ impl EntityType {
                 tags.insert(ActorState::Idle, resources::W_IDLE);
                 tags.insert(ActorState::Running, resources::W_RUN);
                 tags.insert(ActorState::Jumping, resources::W_JUMP);
+                tags.insert(ActorState::Attacking, resources::W_ATTACK);
             }
             EntityType::Bat => {
                 tags.insert(ActorState::Idle, resources::BAT);
             }
+            EntityType::Checkpoint => {
+                tags.insert(ActorState::Idle, resources::CHECKPOINT);
+            }
+            EntityType::Coin => {
+                tags.insert(ActorState::Idle, resources::COIN);
+            }
+            EntityType::Potion => {
+                tags.insert(ActorState::Idle, resources::POTION);
+            }
+            EntityType::Key => {
+                tags.insert(ActorState::Idle, resources::KEY);
+            }
         }
 
         tags
     }
+
+    pub fn boxes(&self) -> HashMap<ActorState, &'static SpriteBoxes> {
+        let mut sprite_boxes = HashMap::new();
+        match self {
+            EntityType::Player => {
+                sprite_boxes.insert(ActorState::Idle, boxes::W_IDLE);
+                sprite_boxes.insert(ActorState::Running, boxes::W_RUN);
+                sprite_boxes.insert(ActorState::Jumping, boxes::W_JUMP);
+                sprite_boxes.insert(ActorState::Attacking, boxes::W_ATTACK);
+            }
+            EntityType::Bat => {
+                sprite_boxes.insert(ActorState::Idle, boxes::BAT);
+            }
+            EntityType::Checkpoint => {
+                sprite_boxes.insert(ActorState::Idle, boxes::CHECKPOINT);
+            }
+            EntityType::Coin => {
+                sprite_boxes.insert(ActorState::Idle, boxes::COIN);
+            }
+            EntityType::Potion => {
+                sprite_boxes.insert(ActorState::Idle, boxes::POTION);
+            }
+            EntityType::Key => {
+                sprite_boxes.insert(ActorState::Idle, boxes::KEY);
+            }
+        }
+
+        sprite_boxes
+    }
+
+    /// Draw order, lower layers are drawn on top: the player above enemies
+    /// above pickups above checkpoints
+    pub fn z_layer(&self) -> i32 {
+        match self {
+            EntityType::Player => 0,
+            EntityType::Bat => 1,
+            EntityType::Coin | EntityType::Potion | EntityType::Key => 2,
+            EntityType::Checkpoint => 3,
+        }
+    }
+
+    /// Whether actors of this type keep simulating when far from the camera,
+    /// whatever their `always_active` property
+    pub fn always_active(&self) -> bool {
+        matches!(self, EntityType::Player)
+    }
+
+    /// Whether the player collects this by touching it
+    pub fn is_pickup(&self) -> bool {
+        matches!(
+            self,
+            EntityType::Coin | EntityType::Potion | EntityType::Key
+        )
+    }
+
+    pub fn contact_filter(&self) -> ContactFilter {
+        match self {
+            EntityType::Player => {
+                ContactFilter::new(layer::PLAYER, layer::ENEMY | layer::PICKUP | layer::TRIGGER)
+            }
+            EntityType::Bat => ContactFilter::new(layer::ENEMY, layer::PLAYER | layer::PROJECTILE),
+            EntityType::Checkpoint => ContactFilter::new(layer::TRIGGER, layer::PLAYER),
+            EntityType::Coin | EntityType::Potion | EntityType::Key => {
+                ContactFilter::new(layer::PICKUP, layer::PLAYER)
+            }
+        }
+    }
+}
+
+/// What happens to an entity when the player respawns at a checkpoint
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Respawn {
+    /// Back at its starting position, even if it was defeated
+    Always,
+    /// Stays gone once defeated
+    Never,
 }
 
 pub struct Entity(
@@ -35,7 +131,11 @@ pub struct Entity(
     pub Vector2D<i32>,
     pub Option<Vector2D<i32>>,
     pub &'static [Behavior],
-    pub Vector2D<i32>,
+    pub Respawn,
+    /// Keeps simulating when far from the camera, set with the `always_active`
+    /// property in Tiled. Types that always need to simulate, like the player,
+    /// do so regardless.
+    pub bool,
 );
 
 pub struct Level {
@@ -44,6 +144,18 @@ pub struct Level {
     pub starting_positions: &'static [Entity],
     pub name: &'static str,
     pub collision_rects: &'static [CollisionRect],
+    pub camera_locks: &'static [Rect<i32>],
+    pub neighbours: &'static [Neighbour],
+    /// Background music, or silence if there is none
+    pub music: Option<Music>,
+}
+
+/// A level next to this one in the world, which the player moves into by
+/// walking off the edge
+pub struct Neighbour {
+    pub level: usize,
+    /// Where the neighbouring level is, relative to this one
+    pub area: Rect<i32>,
 }
 
 pub enum CollisionRect {
@@ -52,13 +164,16 @@ pub enum CollisionRect {
 }
 
 impl Level {
-    #[allow(unused_variables)]
+    #[allow(clippy::too_many_arguments)]
     const fn new(
         width: u32,
         height: u32,
         starting_positions: &'static [Entity],
         name: &'static str,
         collision_rects: &'static [CollisionRect],
+        camera_locks: &'static [Rect<i32>],
+        neighbours: &'static [Neighbour],
+        music: Option<Music>,
     ) -> Self {
         Self {
             width,
@@ -66,9 +181,27 @@ impl Level {
             starting_positions,
             name,
             collision_rects,
+            camera_locks,
+            neighbours,
+            music,
         }
     }
 
+    /// The area covered by the level's tiles, in pixels
+    pub fn bounds(&self) -> Rect<i32> {
+        Rect {
+            position: (0, 0).into(),
+            size: (self.width as i32 * 8, self.height as i32 * 8).into(),
+        }
+    }
+
+    /// The neighbouring level containing a point outside this level
+    pub fn neighbour_at(&self, point: Vector2D<i32>) -> Option<&'static Neighbour> {
+        self.neighbours
+            .iter()
+            .find(|neighbour| neighbour.area.contains_point(point))
+    }
+
     pub fn get_solid_collision_rects(&self) -> Vec<Rect<i32>> {
         self.collision_rects
             .iter()
@@ -89,15 +222,40 @@ impl Level {
             .collect()
     }
 
+    /// Which of the level's pickups the entity at this index is, counting
+    /// only pickups. This is its bit in the save data.
+    pub fn pickup_index(&self, spawn: usize) -> Option<usize> {
+        let entities = self.starting_positions;
+        entities[spawn].0.is_pickup().then(|| {
+            entities[..spawn]
+                .iter()
+                .filter(|entity| entity.0.is_pickup())
+                .count()
+        })
+    }
+
+    /// How many pickups of this type the level has
+    pub fn pickup_count(&self, entity_type: EntityType) -> usize {
+        self.starting_positions
+            .iter()
+            .filter(|entity| entity.0 == entity_type)
+            .count()
+    }
+
     pub fn get_level(level_number: usize) -> &'static Level {
         &levels::LEVELS[level_number]
     }
+
+    pub fn count() -> usize {
+        levels::LEVELS.len()
+    }
 }
 
 mod levels {
     use crate::behaviors::Behavior;
-    use crate::level::{CollisionRect, Level};
-    use crate::level::{Entity, EntityType};
+    use crate::level::{CollisionRect, Level, Neighbour};
+    use crate::level::{Entity, EntityType, Respawn};
+    use crate::sfx::Music;
     use agb::fixnum::{Rect, Vector2D};
 
     include!(concat!(env!("OUT_DIR"), "/levels.rs"));
@@ -5,107 +5,302 @@
 #![cfg_attr(test, test_runner(agb::test_runner::test_runner))]
 #![feature(slice_pattern)]
 
-use core::fmt::Write;
-
 extern crate alloc;
 
 mod actor;
 mod backgrounds;
 mod behaviors;
+mod camera;
 mod close_to_zero;
+mod contact;
 mod game;
+mod hud;
 mod level;
 mod resources;
+mod save;
+mod scene;
 mod sfx;
+mod sprite_boxes;
 mod util;
 
-use agb::display::object::ObjectTextRender;
-use agb::display::object::PaletteVram;
-use agb::display::object::Size;
-use agb::display::object::TextAlignment;
-use agb::display::palette16::Palette16;
+use agb::display::object::OamUnmanaged;
+use agb::display::object::SpriteLoader;
+use agb::display::tiled::Tiled0;
+use agb::display::tiled::VRamManager;
+use agb::display::HEIGHT;
 use agb::display::WIDTH;
 use agb::fixnum::Vector2D;
+use agb::input::Button;
+use agb::input::ButtonController;
 use agb::interrupt::VBlank;
-use agb::mgba::Mgba;
 use agb::sound::mixer::Frequency;
+use alloc::vec::Vec;
 
-use game::Game;
+use game::{Game, LevelStart, LevelStatus};
+use hud::Hud;
 use level::Level;
+use save::{Progress, SaveFile, Saves, SlotState, SLOTS};
+use scene::{Scene, SceneChange, SceneStack};
+use sfx::Sfx;
 
 pub fn entry(mut gba: agb::Gba) -> ! {
-    let mut logger = Mgba::new();
     let vblank = VBlank::get();
     let (mut unmanaged, mut sprite_loader) = gba.display.object.get_unmanaged();
 
     let mut mixer = gba.mixer.mixer(Frequency::Hz32768);
     mixer.enable();
 
-    let mut sfx = sfx::Sfx::new(&mut mixer);
-    //sfx.crawl();
+    let mut sfx = Sfx::new(&mut mixer);
+    let mut progress = Progress {
+        saves: Saves::new(&mut gba.save),
+        slot: 0,
+        file: SaveFile::new(),
+    };
+    let mut saved_settings = progress.saves.load_settings();
+    sfx.set_settings(saved_settings);
 
     let (tiled, mut vram) = gba.display.video.tiled0();
 
-    backgrounds::load_palettes(&mut vram);
-
-    let mut palette = [0x0; 16];
-    palette[1] = 0xFF_FF;
-    let palette = Palette16::new(palette);
-    let palette = PaletteVram::new(&palette).unwrap();
-    let mut writer = ObjectTextRender::new(&resources::FONT, Size::S16x16, palette);
-    let _ = writeln!(writer, "Hello, World!");
-    writer.layout((WIDTH, 40), TextAlignment::Left, 2);
+    backgrounds::load_palettes(&mut vram, 0);
 
-    let current_level = 1;
+    let mut scenes = SceneStack::new(Scene::Title);
+    let mut room_entry = None;
 
     loop {
-        let level = Level::get_level(current_level);
-        let (mut bg2, mut bg3) = backgrounds::load_backgrounds(current_level, level, &tiled);
+        let change = match scenes.current() {
+            Scene::Title => scene::title(&vblank, &mut unmanaged, &mut sfx),
+            Scene::SlotSelect => {
+                let slots = (0..SLOTS)
+                    .map(|slot| progress.saves.load(slot))
+                    .collect::<Vec<_>>();
+                let change = scene::slot_select(
+                    &vblank,
+                    &mut unmanaged,
+                    &mut sfx,
+                    &slots,
+                    &mut progress.slot,
+                );
+                if change == SceneChange::Replace(Scene::LevelSelect) {
+                    progress.file = match &slots[progress.slot] {
+                        SlotState::Saved(file) => file.clone(),
+                        SlotState::Empty | SlotState::Corrupted => SaveFile::new(),
+                    };
+                }
+                change
+            }
+            Scene::LevelSelect => scene::level_select(
+                &vblank,
+                &mut unmanaged,
+                &mut sfx,
+                progress.file.unlocked_levels,
+            ),
+            Scene::GameOver(level_number) => {
+                scene::game_over(&vblank, &mut unmanaged, &mut sfx, level_number)
+            }
+            Scene::Playing(level_number) => {
+                let start = match (room_entry.take(), progress.file.checkpoint) {
+                    (Some(entry), _) => LevelStart::Room(entry),
+                    (None, Some((level, checkpoint))) if level == level_number => {
+                        LevelStart::Checkpoint(checkpoint)
+                    }
+                    (None, _) => LevelStart::Spawn,
+                };
+                let status = play_level(
+                    level_number,
+                    start,
+                    &mut scenes,
+                    &vblank,
+                    &tiled,
+                    &mut vram,
+                    &mut unmanaged,
+                    &mut sprite_loader,
+                    &mut sfx,
+                    &mut progress,
+                );
+
+                match status {
+                    LevelStatus::Complete(frames) => {
+                        let file = &mut progress.file;
+                        file.record_time(level_number, frames);
+                        file.unlocked_levels = file.unlocked_levels.max(level_number + 2);
+                        if file
+                            .checkpoint
+                            .is_some_and(|(level, _)| level == level_number)
+                        {
+                            file.checkpoint = None;
+                        }
+                        progress.save();
 
-        let mut between_updates = || {
-            sfx.frame();
-            vblank.wait_for_vblank();
+                        if level_number + 1 < Level::count() {
+                            SceneChange::Replace(Scene::Playing(level_number + 1))
+                        } else {
+                            SceneChange::Pop
+                        }
+                    }
+                    LevelStatus::Dead => {
+                        sfx.stop_music();
+                        SceneChange::Replace(Scene::GameOver(level_number))
+                    }
+                    LevelStatus::Travel(entry) => {
+                        progress.file.unlocked_levels =
+                            progress.file.unlocked_levels.max(entry.level + 1);
+                        progress.save();
+                        room_entry = Some(entry);
+                        SceneChange::Replace(Scene::Playing(entry.level))
+                    }
+                    LevelStatus::Quit => {
+                        sfx.stop_music();
+                        SceneChange::Replace(Scene::LevelSelect)
+                    }
+                    LevelStatus::Playing => SceneChange::None,
+                }
+            }
+            Scene::AudioSettings => scene::audio_settings(&vblank, &mut unmanaged, &mut sfx),
+            Scene::Paused => SceneChange::Pop,
         };
 
-        let start_pos = (0, 0).into();
-        bg2.init(&mut vram, start_pos, &mut between_updates);
-        bg3.init(&mut vram, start_pos, &mut between_updates);
+        scenes.apply(change);
 
-        bg2.commit(&mut vram);
-        bg3.commit(&mut vram);
+        if sfx.settings() != saved_settings {
+            saved_settings = sfx.settings();
+            if progress.saves.save_settings(&saved_settings).is_err() {
+                agb::println!("Failed to save settings");
+            }
+        }
+    }
+}
 
-        bg2.set_visible(true);
-        bg3.set_visible(true);
+/// Play a level until it is completed, lost, left or quit from the pause menu.
+/// `start` places the player, such as when arriving from a neighbouring level.
+/// Checkpoints are saved as soon as they are touched, along with the pickups
+/// collected so far. Pickups are also kept when the level is completed or left.
+#[allow(clippy::too_many_arguments)]
+fn play_level(
+    level_number: usize,
+    start: LevelStart,
+    scenes: &mut SceneStack,
+    vblank: &VBlank,
+    tiled: &Tiled0,
+    vram: &mut VRamManager,
+    unmanaged: &mut OamUnmanaged,
+    sprite_loader: &mut SpriteLoader,
+    sfx: &mut Sfx,
+    progress: &mut Progress,
+) -> LevelStatus {
+    let level = Level::get_level(level_number);
+    let mut backgrounds = backgrounds::load_backgrounds(level_number, level, tiled, vram);
+    sfx.play_music(level.music);
 
-        let mut game = Game::new(level);
-        game.load_level_assets();
+    let mut between_updates = || {
+        sfx.frame();
+        vblank.wait_for_vblank();
+    };
 
-        loop {
-            writer.next_letter_group();
-            writer.update((0, 0));
-            sfx.frame();
+    backgrounds.init(vram, &mut between_updates);
+    backgrounds.commit(vram);
+    backgrounds.set_visible(true);
 
-            vblank.wait_for_vblank();
-            bg2.commit(&mut vram);
-            bg3.commit(&mut vram);
+    let mut game = Game::new(level);
+    game.load_level_assets();
+    if let Some(&collected) = progress.file.collected.get(level_number) {
+        game.remove_collected(collected);
+    }
+    match start {
+        LevelStart::Spawn => {}
+        LevelStart::Room(entry) => game.enter_room(&entry),
+        LevelStart::Checkpoint(checkpoint) => game.respawn_at(&checkpoint),
+    }
 
-            let oam = &mut unmanaged.iter();
+    let mut hud = Hud::new(level);
+    game.sprite_budget -= hud::HUD_SPRITES;
 
-            game.update(&mut sfx);
+    let mut input = ButtonController::new();
+    let mut pause_text = scene::text_render(
+        "Paused\n\nStart: resume\nA: audio\nSelect: quit",
+        (WIDTH, HEIGHT - 40),
+    );
 
-            // Update scroll
-            bg2.set_pos(&mut vram, -game.scroll_pos);
-            bg3.set_pos(
-                &mut vram,
-                -Vector2D {
-                    x: (game.scroll_pos.x * 6) / 10,
-                    y: (game.scroll_pos.y * 8) / 10,
-                },
-            );
+    let status = loop {
+        sfx.frame();
 
-            game.render(&mut sprite_loader, oam);
+        vblank.wait_for_vblank();
+        backgrounds.commit(vram);
+        backgrounds.animate_tiles(vram);
 
-            writer.commit(oam);
+        input.update();
+        match scenes.current() {
+            Scene::Playing(_) => {
+                if input.is_just_pressed(Button::START) {
+                    scenes.push(Scene::Paused);
+                    sfx.pause();
+                }
+            }
+            Scene::Paused => {
+                if input.is_just_pressed(Button::START) {
+                    scenes.pop();
+                    sfx.resume();
+                } else if input.is_just_pressed(Button::A) {
+                    // Run here instead of from the main loop so that the level
+                    // stays loaded behind the menu
+                    scenes.push(Scene::AudioSettings);
+                    let change = scene::audio_settings(vblank, unmanaged, sfx);
+                    scenes.apply(change);
+                    // Catch up with the button that closed the menu so it
+                    // doesn't also resume
+                    input.update();
+                } else if input.is_just_pressed(Button::SELECT) {
+                    scenes.pop();
+                    sfx.resume();
+                    break LevelStatus::Quit;
+                }
+            }
+            _ => break LevelStatus::Playing,
         }
-    }
+
+        let oam = &mut unmanaged.iter();
+        if scenes.current() == Scene::Paused {
+            pause_text.next_letter_group();
+            pause_text.update((0, 40));
+            pause_text.commit(oam);
+        } else {
+            game.update(sfx);
+            hud.update(&game);
+
+            let checkpoint = game
+                .checkpoint()
+                .map(|checkpoint| (level_number, checkpoint));
+            // Changes when a checkpoint is touched or an entity that stays
+            // defeated is defeated after it
+            let checkpoint_changed = checkpoint.is_some() && checkpoint != progress.file.checkpoint;
+            let left_level = matches!(
+                game.status(),
+                LevelStatus::Complete(_) | LevelStatus::Travel(_)
+            );
+            if checkpoint_changed || left_level {
+                if let Some(collected) = progress.file.collected.get_mut(level_number) {
+                    *collected |= game.collected();
+                }
+            }
+            if checkpoint_changed {
+                progress.file.checkpoint = checkpoint;
+                progress.save();
+            }
+
+            if game.status() != LevelStatus::Playing {
+                break game.status();
+            }
+        }
+
+        // Update scroll
+        backgrounds.set_scroll(vram, game.scroll_pos, game.screen_shake());
+
+        hud.render(&game, sprite_loader, oam);
+        game.render(sprite_loader, oam);
+    };
+
+    backgrounds.set_visible(false);
+    backgrounds.clear(vram);
+    backgrounds.commit(vram);
+
+    status
 }
@@ -23,4 +23,20 @@ macro_rules! named_tag {
     };
 }
 
-named_tag!(SPRITES, [W_IDLE, W_RUN, W_JUMP, BAT,]);
+named_tag!(
+    SPRITES,
+    [
+        W_IDLE,
+        W_RUN,
+        W_JUMP,
+        W_ATTACK,
+        BAT,
+        CHECKPOINT,
+        CHECKPOINT_LIT,
+        COIN,
+        POTION,
+        KEY,
+        HEART,
+        HEART_EMPTY,
+    ]
+);
@@ -0,0 +1,351 @@
+use crate::game::Checkpoint;
+use crate::sfx::AudioSettings;
+use agb::save::{Error, SaveData, SaveManager};
+use alloc::vec::Vec;
+
+/// How many games can be saved at once
+pub const SLOTS: usize = 3;
+
+/// How many levels the save format has room for
+pub const MAX_LEVELS: usize = 16;
+
+/// Every record starts with this, so blank or foreign SRAM isn't read as a save
+const MAGIC: [u8; 4] = *b"WHCH";
+
+/// Bump this whenever fields are added to the end of a record. Older records
+/// still load, with the newer fields left at their defaults.
+///
+/// 2. Added the last checkpoint touched
+const VERSION: u16 = 2;
+
+/// Magic, version, payload length, sequence number and checksum
+const HEADER_SIZE: usize = 16;
+
+/// The most a record can take up, including its header
+const COPY_SIZE: usize = 256;
+
+/// Each record is written to alternate copies, so losing power halfway through
+/// a write still leaves the previous save intact
+const RECORD_SIZE: usize = COPY_SIZE * 2;
+
+/// The record after the save slots, holding settings shared by every slot
+const SETTINGS_RECORD: usize = SLOTS;
+
+/// One saved game
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SaveFile {
+    pub unlocked_levels: usize,
+    /// The fewest frames each level has been completed in, or 0 if it hasn't
+    pub best_times: [u32; MAX_LEVELS],
+    /// A bit for each pickup collected in each level, by `Level::pickup_index`
+    pub collected: [u32; MAX_LEVELS],
+    /// The level and checkpoint to continue from
+    pub checkpoint: Option<(usize, Checkpoint)>,
+}
+
+impl SaveFile {
+    pub fn new() -> Self {
+        Self {
+            unlocked_levels: 1,
+            best_times: [0; MAX_LEVELS],
+            collected: [0; MAX_LEVELS],
+            checkpoint: None,
+        }
+    }
+
+    /// Keep the time if it is the best one for the level
+    pub fn record_time(&mut self, level: usize, frames: u32) {
+        if let Some(best) = self.best_times.get_mut(level) {
+            if *best == 0 || frames < *best {
+                *best = frames;
+            }
+        }
+    }
+
+    fn write(&self, writer: &mut Vec<u8>) {
+        writer.push(self.unlocked_levels.min(MAX_LEVELS) as u8);
+        for time in self.best_times {
+            writer.extend_from_slice(&time.to_le_bytes());
+        }
+        for collected in self.collected {
+            writer.extend_from_slice(&collected.to_le_bytes());
+        }
+        let checkpoint = self.checkpoint.and_then(|(level, checkpoint)| {
+            Some((
+                u8::try_from(level).ok()?,
+                u8::try_from(checkpoint.spawn).ok()?,
+                checkpoint.defeated,
+            ))
+        });
+        match checkpoint {
+            Some((level, spawn, defeated)) => {
+                writer.extend_from_slice(&[1, level, spawn]);
+                writer.extend_from_slice(&defeated.to_le_bytes());
+            }
+            None => {
+                debug_assert!(self.checkpoint.is_none(), "Checkpoint can't be saved");
+                writer.push(0);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Self {
+        let mut file = Self::new();
+        if let Some(unlocked_levels) = reader.u8() {
+            file.unlocked_levels = (unlocked_levels as usize).max(1);
+        }
+        for time in file.best_times.iter_mut() {
+            *time = reader.u32().unwrap_or(*time);
+        }
+        for collected in file.collected.iter_mut() {
+            *collected = reader.u32().unwrap_or(*collected);
+        }
+        if reader.u8() == Some(1) {
+            file.checkpoint = read_checkpoint(reader);
+        }
+        file
+    }
+}
+
+fn read_checkpoint(reader: &mut Reader) -> Option<(usize, Checkpoint)> {
+    let level = reader.u8()? as usize;
+    let spawn = reader.u8()? as usize;
+    let defeated = reader.u64()?;
+    Some((level, Checkpoint { spawn, defeated }))
+}
+
+fn write_settings(settings: &AudioSettings, writer: &mut Vec<u8>) {
+    writer.extend_from_slice(&[
+        settings.master,
+        settings.music,
+        settings.effects,
+        settings.muted as u8,
+    ]);
+}
+
+fn read_settings(reader: &mut Reader) -> AudioSettings {
+    let defaults = AudioSettings::new();
+    AudioSettings {
+        master: reader.u8().unwrap_or(defaults.master),
+        music: reader.u8().unwrap_or(defaults.music),
+        effects: reader.u8().unwrap_or(defaults.effects),
+        muted: reader.u8().map_or(defaults.muted, |muted| muted != 0),
+    }
+}
+
+/// Reads fields from a record's payload. Fields past the end of an older,
+/// shorter payload come back as `None`.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let (&value, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let (value, rest) = self.data.split_first_chunk::<4>()?;
+        self.data = rest;
+        Some(u32::from_le_bytes(*value))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let (value, rest) = self.data.split_first_chunk::<8>()?;
+        self.data = rest;
+        Some(u64::from_le_bytes(*value))
+    }
+}
+
+/// The game being played, and the slot it saves to
+pub struct Progress {
+    pub saves: Saves,
+    pub slot: usize,
+    pub file: SaveFile,
+}
+
+impl Progress {
+    pub fn save(&mut self) {
+        if self.saves.save(self.slot, &self.file).is_err() {
+            agb::println!("Failed to save slot {}", self.slot + 1);
+        }
+    }
+}
+
+/// What was found in a save slot
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SlotState {
+    Empty,
+    Saved(SaveFile),
+    /// Something was saved but neither copy can be read back
+    Corrupted,
+}
+
+/// A record's payload if either of its copies is valid, preferring the newest
+enum Record {
+    Empty,
+    Valid {
+        copy: usize,
+        sequence: u32,
+        payload: Vec<u8>,
+    },
+    Corrupted,
+}
+
+/// Save slots and settings kept in battery backed SRAM
+pub struct Saves {
+    save_data: Option<SaveData>,
+}
+
+impl Saves {
+    /// Without SRAM, such as on a flash cart with saving turned off, the game
+    /// still runs but nothing is kept
+    pub fn new(save: &mut SaveManager) -> Self {
+        save.init_sram();
+        let save_data = save.access().ok();
+        if save_data.is_none() {
+            agb::println!("No save media, progress won't be kept");
+        }
+
+        Self { save_data }
+    }
+
+    pub fn load(&mut self, slot: usize) -> SlotState {
+        match self.read_record(slot) {
+            Record::Empty => SlotState::Empty,
+            Record::Valid { payload, .. } => {
+                SlotState::Saved(SaveFile::read(&mut Reader { data: &payload }))
+            }
+            Record::Corrupted => SlotState::Corrupted,
+        }
+    }
+
+    pub fn save(&mut self, slot: usize, file: &SaveFile) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        file.write(&mut payload);
+        self.write_record(slot, &payload)
+    }
+
+    /// The settings last saved, or the defaults if there aren't any
+    pub fn load_settings(&mut self) -> AudioSettings {
+        match self.read_record(SETTINGS_RECORD) {
+            Record::Valid { payload, .. } => read_settings(&mut Reader { data: &payload }),
+            Record::Empty | Record::Corrupted => AudioSettings::new(),
+        }
+    }
+
+    pub fn save_settings(&mut self, settings: &AudioSettings) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        write_settings(settings, &mut payload);
+        self.write_record(SETTINGS_RECORD, &payload)
+    }
+
+    fn read_record(&mut self, record: usize) -> Record {
+        let Some(save_data) = self.save_data.as_mut() else {
+            return Record::Empty;
+        };
+
+        let mut found_any = false;
+        let mut newest: Option<(usize, u32, Vec<u8>)> = None;
+        for copy in 0..2 {
+            let mut buffer = [0; COPY_SIZE];
+            if save_data
+                .read(record * RECORD_SIZE + copy * COPY_SIZE, &mut buffer)
+                .is_err()
+            {
+                continue;
+            }
+
+            if buffer[0..4] != MAGIC {
+                continue;
+            }
+            found_any = true;
+
+            let Some((sequence, payload)) = parse_copy(&buffer) else {
+                continue;
+            };
+            if newest
+                .as_ref()
+                .map_or(true, |(_, newest, _)| sequence_after(sequence, *newest))
+            {
+                newest = Some((copy, sequence, payload.to_vec()));
+            }
+        }
+
+        match newest {
+            Some((copy, sequence, payload)) => Record::Valid {
+                copy,
+                sequence,
+                payload,
+            },
+            None if found_any => Record::Corrupted,
+            None => Record::Empty,
+        }
+    }
+
+    /// Write over the older of the record's two copies
+    fn write_record(&mut self, record: usize, payload: &[u8]) -> Result<(), Error> {
+        assert!(
+            HEADER_SIZE + payload.len() <= COPY_SIZE,
+            "Save record is too big"
+        );
+
+        let (copy, sequence) = match self.read_record(record) {
+            Record::Valid { copy, sequence, .. } => (1 - copy, sequence.wrapping_add(1)),
+            Record::Empty | Record::Corrupted => (0, 0),
+        };
+
+        let Some(save_data) = self.save_data.as_mut() else {
+            return Ok(());
+        };
+
+        let mut buffer = Vec::with_capacity(HEADER_SIZE + payload.len());
+        buffer.extend_from_slice(&MAGIC);
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        buffer.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        buffer.extend_from_slice(&sequence.to_le_bytes());
+        buffer.extend_from_slice(&checksum(&buffer[4..12], payload).to_le_bytes());
+        buffer.extend_from_slice(payload);
+
+        let offset = record * RECORD_SIZE + copy * COPY_SIZE;
+        let mut block = save_data.prepare_write(offset..offset + COPY_SIZE)?;
+        block.write_and_verify(offset, &buffer)
+    }
+}
+
+/// The sequence number and payload of one copy of a record, if its checksum
+/// matches and it isn't from a newer version of the game
+fn parse_copy(buffer: &[u8; COPY_SIZE]) -> Option<(u32, &[u8])> {
+    let version = u16::from_le_bytes([buffer[4], buffer[5]]);
+    let length = u16::from_le_bytes([buffer[6], buffer[7]]) as usize;
+    let sequence = u32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+    let stored_checksum = u32::from_le_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]);
+
+    if version > VERSION || HEADER_SIZE + length > COPY_SIZE {
+        return None;
+    }
+
+    let payload = &buffer[HEADER_SIZE..HEADER_SIZE + length];
+    if checksum(&buffer[4..12], payload) != stored_checksum {
+        return None;
+    }
+
+    Some((sequence, payload))
+}
+
+/// Whether `sequence` was written after `other`, allowing for wrapping
+fn sequence_after(sequence: u32, other: u32) -> bool {
+    (sequence.wrapping_sub(other) as i32) > 0
+}
+
+/// FNV-1a over the header fields and the payload
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    header
+        .iter()
+        .chain(payload)
+        .fold(0x811c_9dc5, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
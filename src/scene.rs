@@ -0,0 +1,325 @@
+use crate::level::Level;
+use crate::resources;
+use crate::save::SlotState;
+use crate::sfx::{Sfx, Sound, VOLUME_STEPS};
+use agb::display::object::{OamUnmanaged, ObjectTextRender, PaletteVram, Size, TextAlignment};
+use agb::display::palette16::Palette16;
+use agb::display::{HEIGHT, WIDTH};
+use agb::input::{Button, ButtonController, Tri};
+use agb::interrupt::VBlank;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scene {
+    Title,
+    SlotSelect,
+    LevelSelect,
+    Playing(usize),
+    Paused,
+    AudioSettings,
+    GameOver(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SceneChange {
+    None,
+    Push(Scene),
+    Pop,
+    Replace(Scene),
+}
+
+pub struct SceneStack {
+    scenes: Vec<Scene>,
+}
+
+impl SceneStack {
+    pub fn new(scene: Scene) -> Self {
+        Self {
+            scenes: vec![scene],
+        }
+    }
+
+    pub fn current(&self) -> Scene {
+        *self
+            .scenes
+            .last()
+            .expect("The scene stack should never be empty")
+    }
+
+    pub fn push(&mut self, scene: Scene) {
+        self.scenes.push(scene);
+    }
+
+    /// Remove the current scene, falling back to the title screen if it was the last one
+    pub fn pop(&mut self) {
+        self.scenes.pop();
+        if self.scenes.is_empty() {
+            self.scenes.push(Scene::Title);
+        }
+    }
+
+    pub fn replace(&mut self, scene: Scene) {
+        self.scenes.pop();
+        self.scenes.push(scene);
+    }
+
+    pub fn apply(&mut self, change: SceneChange) {
+        match change {
+            SceneChange::None => {}
+            SceneChange::Push(scene) => self.push(scene),
+            SceneChange::Pop => self.pop(),
+            SceneChange::Replace(scene) => self.replace(scene),
+        }
+    }
+}
+
+/// Lay out white text with the game font for the title, pause and menu screens
+pub fn text_render(text: &str, area: (i32, i32)) -> ObjectTextRender<'static> {
+    let mut palette = [0x0; 16];
+    palette[1] = 0xFF_FF;
+    let palette = Palette16::new(palette);
+    let palette = PaletteVram::new(&palette).unwrap();
+
+    let mut writer = ObjectTextRender::new(&resources::FONT, Size::S16x16, palette);
+    let _ = write!(writer, "{text}");
+    writer.layout(area, TextAlignment::Center, 2);
+    writer
+}
+
+enum MenuAction {
+    None,
+    Redraw,
+    Change(SceneChange),
+}
+
+/// Show a full screen of text until `select` picks the next scene
+fn menu<T>(
+    vblank: &VBlank,
+    unmanaged: &mut OamUnmanaged,
+    sfx: &mut Sfx,
+    state: &mut T,
+    text: impl Fn(&T) -> String,
+    mut select: impl FnMut(&mut T, &ButtonController, &mut Sfx) -> MenuAction,
+) -> SceneChange {
+    let mut input = ButtonController::new();
+    let mut writer = text_render(&text(state), (WIDTH, HEIGHT - 40));
+
+    loop {
+        writer.next_letter_group();
+        writer.update((0, 40));
+        sfx.frame();
+
+        vblank.wait_for_vblank();
+        let oam = &mut unmanaged.iter();
+        writer.commit(oam);
+
+        input.update();
+        match select(state, &input, sfx) {
+            MenuAction::None => {}
+            MenuAction::Redraw => writer = text_render(&text(state), (WIDTH, HEIGHT - 40)),
+            MenuAction::Change(change) => return change,
+        }
+    }
+}
+
+pub fn title(vblank: &VBlank, unmanaged: &mut OamUnmanaged, sfx: &mut Sfx) -> SceneChange {
+    menu(
+        vblank,
+        unmanaged,
+        sfx,
+        &mut (),
+        |_| "Whitch\n\nPress Start\nSelect: audio".into(),
+        |_, input, _| {
+            if input.is_just_pressed(Button::START | Button::A) {
+                MenuAction::Change(SceneChange::Push(Scene::SlotSelect))
+            } else if input.is_just_pressed(Button::SELECT) {
+                MenuAction::Change(SceneChange::Push(Scene::AudioSettings))
+            } else {
+                MenuAction::None
+            }
+        },
+    )
+}
+
+pub fn game_over(
+    vblank: &VBlank,
+    unmanaged: &mut OamUnmanaged,
+    sfx: &mut Sfx,
+    level: usize,
+) -> SceneChange {
+    menu(
+        vblank,
+        unmanaged,
+        sfx,
+        &mut (),
+        |_| "Game Over\n\nStart: try again\nB: title screen".into(),
+        |_, input, _| {
+            if input.is_just_pressed(Button::START | Button::A) {
+                MenuAction::Change(SceneChange::Replace(Scene::Playing(level)))
+            } else if input.is_just_pressed(Button::B) {
+                MenuAction::Change(SceneChange::Pop)
+            } else {
+                MenuAction::None
+            }
+        },
+    )
+}
+
+/// Pick a save slot to play from, leaving its number in `slot`. Corrupted
+/// slots can still be picked, to start again in them.
+pub fn slot_select(
+    vblank: &VBlank,
+    unmanaged: &mut OamUnmanaged,
+    sfx: &mut Sfx,
+    slots: &[SlotState],
+    slot: &mut usize,
+) -> SceneChange {
+    menu(
+        vblank,
+        unmanaged,
+        sfx,
+        slot,
+        |selected| {
+            let mut text = String::new();
+            for (slot, state) in slots.iter().enumerate() {
+                let cursor = if slot == *selected { "> " } else { "" };
+                let _ = match state {
+                    SlotState::Empty => writeln!(text, "{cursor}{}: new game", slot + 1),
+                    SlotState::Saved(file) => writeln!(
+                        text,
+                        "{cursor}{}: {} levels",
+                        slot + 1,
+                        file.unlocked_levels
+                    ),
+                    SlotState::Corrupted => writeln!(text, "{cursor}{}: damaged", slot + 1),
+                };
+            }
+            text
+        },
+        |selected, input, _| {
+            if input.is_just_pressed(Button::START | Button::A) {
+                return MenuAction::Change(SceneChange::Replace(Scene::LevelSelect));
+            }
+            if input.is_just_pressed(Button::B) {
+                return MenuAction::Change(SceneChange::Pop);
+            }
+
+            match input.just_pressed_y_tri() {
+                Tri::Negative if *selected > 0 => *selected -= 1,
+                Tri::Positive if *selected + 1 < slots.len() => *selected += 1,
+                _ => return MenuAction::None,
+            }
+            MenuAction::Redraw
+        },
+    )
+}
+
+/// Pick one of the first `unlocked_levels` levels to play
+pub fn level_select(
+    vblank: &VBlank,
+    unmanaged: &mut OamUnmanaged,
+    sfx: &mut Sfx,
+    unlocked_levels: usize,
+) -> SceneChange {
+    let unlocked_levels = unlocked_levels.clamp(1, Level::count());
+
+    menu(
+        vblank,
+        unmanaged,
+        sfx,
+        &mut 0,
+        |selected| {
+            let mut text = String::new();
+            for level_number in 0..unlocked_levels {
+                let cursor = if level_number == *selected { "> " } else { "" };
+                let _ = writeln!(text, "{cursor}{}", Level::get_level(level_number).name);
+            }
+            text
+        },
+        |selected, input, _| {
+            if input.is_just_pressed(Button::START | Button::A) {
+                return MenuAction::Change(SceneChange::Replace(Scene::Playing(*selected)));
+            }
+            if input.is_just_pressed(Button::B) {
+                return MenuAction::Change(SceneChange::Pop);
+            }
+
+            match input.just_pressed_y_tri() {
+                Tri::Negative if *selected > 0 => *selected -= 1,
+                Tri::Positive if *selected + 1 < unlocked_levels => *selected += 1,
+                _ => return MenuAction::None,
+            }
+            MenuAction::Redraw
+        },
+    )
+}
+
+/// Adjust the master, music and effects volumes and mute. Changes take effect
+/// straight away so they can be heard.
+pub fn audio_settings(vblank: &VBlank, unmanaged: &mut OamUnmanaged, sfx: &mut Sfx) -> SceneChange {
+    let settings = sfx.settings();
+
+    menu(
+        vblank,
+        unmanaged,
+        sfx,
+        &mut (0, settings),
+        |(selected, settings)| {
+            let rows = [
+                format!("Master {}/{VOLUME_STEPS}", settings.master),
+                format!("Music {}/{VOLUME_STEPS}", settings.music),
+                format!("Effects {}/{VOLUME_STEPS}", settings.effects),
+                format!("Sound {}", if settings.muted { "off" } else { "on" }),
+            ];
+
+            let mut text = String::from("Audio\n\n");
+            for (row, label) in rows.iter().enumerate() {
+                let cursor = if row == *selected { "> " } else { "" };
+                let _ = writeln!(text, "{cursor}{label}");
+            }
+            text
+        },
+        |(selected, settings), input, sfx| {
+            if input.is_just_pressed(Button::START | Button::B) {
+                return MenuAction::Change(SceneChange::Pop);
+            }
+
+            match input.just_pressed_y_tri() {
+                Tri::Negative if *selected > 0 => {
+                    *selected -= 1;
+                    return MenuAction::Redraw;
+                }
+                Tri::Positive if *selected < 3 => {
+                    *selected += 1;
+                    return MenuAction::Redraw;
+                }
+                _ => {}
+            }
+
+            let change = input.just_pressed_x_tri() as i32;
+            let adjust = |level: &mut u8| {
+                *level = (*level as i32 + change).clamp(0, VOLUME_STEPS as i32) as u8;
+            };
+            match *selected {
+                0 => adjust(&mut settings.master),
+                1 => adjust(&mut settings.music),
+                2 => adjust(&mut settings.effects),
+                _ if change != 0 || input.is_just_pressed(Button::A) => {
+                    settings.muted = !settings.muted
+                }
+                _ => {}
+            }
+
+            if *settings == sfx.settings() {
+                return MenuAction::None;
+            }
+            sfx.set_settings(*settings);
+            sfx.play(Sound::Jump);
+            MenuAction::Redraw
+        },
+    )
+}
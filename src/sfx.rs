@@ -1,3 +1,5 @@
+use agb::display::WIDTH;
+use agb::fixnum::{Num, Vector2D};
 use agb::rng;
 use agb::sound::mixer::{ChannelId, Mixer, SoundChannel};
 use agb_tracker::include_xm;
@@ -5,16 +7,158 @@ use agb_tracker::Track;
 use agb_tracker::Tracker;
 use alloc::vec::Vec;
 
-static BAT_FLAP: &[u8] = agb::include_wav!("sfx/BatFlap.wav");
-static JUMP1: &[u8] = agb::include_wav!("sfx/Jump1.wav");
-static JUMP2: &[u8] = agb::include_wav!("sfx/Jump2.wav");
-static JUMP3: &[u8] = agb::include_wav!("sfx/Jump3.wav");
+pub use sounds::Sound;
 
 static CRAWL_XM: Track = include_xm!("music/crawl.xm");
 
+/// How many frames music takes to fade out, and the next track to fade in
+const MUSIC_FADE_FRAMES: u16 = 30;
+
+/// Sounds closer than this to the middle of the screen play at full volume
+const FULL_VOLUME_DISTANCE: i32 = 64;
+
+/// Sounds further than this from the middle of the screen are not played
+const HEARING_DISTANCE: i32 = 200;
+
+/// How far to the side of the middle of the screen a sound has to be to
+/// play entirely in one speaker
+const FULL_PAN_DISTANCE: i32 = WIDTH / 2;
+
+/// How many sound effects can play at once. The rest of the mixer's 8 channels
+/// are left for the music.
+const EFFECT_VOICES: usize = 4;
+
+/// How many steps each volume setting has, from silent to full
+pub const VOLUME_STEPS: u8 = 8;
+
+/// How loud the music is while ducked, under an effect or dialogue
+const DUCK_VOLUME: Num<i32, 8> = Num::from_raw(96);
+
+/// How many frames the music takes to duck down, and to come back up again
+const DUCK_FRAMES: i32 = 8;
+
+/// Volume levels for each group of sounds, out of `VOLUME_STEPS`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AudioSettings {
+    pub master: u8,
+    pub music: u8,
+    pub effects: u8,
+    /// Silence everything without losing the volume levels
+    pub muted: bool,
+}
+
+impl AudioSettings {
+    pub fn new() -> Self {
+        Self {
+            master: VOLUME_STEPS,
+            music: VOLUME_STEPS,
+            effects: VOLUME_STEPS,
+            muted: false,
+        }
+    }
+
+    fn music_volume(&self) -> Num<i32, 8> {
+        self.group_volume(self.music)
+    }
+
+    fn effects_volume(&self) -> Num<i32, 8> {
+        self.group_volume(self.effects)
+    }
+
+    fn group_volume(&self, level: u8) -> Num<i32, 8> {
+        if self.muted {
+            return 0.into();
+        }
+        let steps = VOLUME_STEPS as i32;
+        Num::new(self.master.min(VOLUME_STEPS) as i32 * level.min(VOLUME_STEPS) as i32)
+            / (steps * steps)
+    }
+}
+
+/// A background music track, chosen per level with the `music` property in Tiled
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Music {
+    Crawl,
+}
+
+impl Music {
+    fn track(self) -> &'static Track<'static> {
+        match self {
+            Music::Crawl => &CRAWL_XM,
+        }
+    }
+
+    /// How many rows of the track make up one beat
+    fn rows_per_beat(self) -> usize {
+        match self {
+            Music::Crawl => 4,
+        }
+    }
+}
+
+/// Something that happened in the music this frame, for gameplay to keep time
+/// with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MusicEvent {
+    /// A beat started, counting from the start of the track
+    Beat(u32),
+}
+
+/// The samples and settings for a sound, from the files in `sfx/` and
+/// `sfx/sounds.json`
+pub struct SoundInfo {
+    /// One of these is picked at random each time the sound plays
+    pub variations: &'static [&'static [u8]],
+    pub volume: Num<i16, 8>,
+    /// When every effect voice is busy, a sound cuts off the oldest one with
+    /// the lowest priority, as long as that is no higher than its own
+    pub priority: u8,
+    /// How many of this sound can play at once before the oldest is cut off
+    pub max_instances: u8,
+    /// Whether the music ducks while this sound plays
+    pub ducks_music: bool,
+    /// The range of playback speeds picked from at random
+    pub pitch: (Num<u32, 8>, Num<u32, 8>),
+}
+
+mod sounds {
+    use super::SoundInfo;
+
+    include!(concat!(env!("OUT_DIR"), "/sounds.rs"));
+}
+
+/// A sound effect that is still playing
+struct Effect {
+    id: ChannelId,
+    sound: Sound,
+}
+
 pub struct Sfx<'a> {
-    bgm: Option<ChannelId>,
-    trackers: Vec<Tracker>,
+    music: Option<Music>,
+    tracker: Option<Tracker>,
+    /// Music waiting for the current track to fade out
+    next_music: Option<Option<Music>>,
+    /// How far the music has faded in, before ducking and settings
+    music_volume: Num<i32, 8>,
+    /// How far the music is ducked, from 1 down to `DUCK_VOLUME`
+    duck_volume: Num<i32, 8>,
+    /// Keep the music ducked, such as while dialogue is on screen
+    ducked: bool,
+    settings: AudioSettings,
+    /// What happened in the music during the last frame
+    music_events: Vec<MusicEvent>,
+    /// How many beats have started since the track did
+    beats: u32,
+    /// How many frames music fades out and in over when it changes
+    pub fade_frames: u16,
+    /// Sound effects that are playing, oldest first
+    effects: Vec<Effect>,
+    paused: bool,
+    /// Where in the world sounds are heard from, usually the middle of the
+    /// screen
+    listener: Vector2D<i32>,
+    /// Sounds further away than this are not played
+    pub hearing_distance: i32,
     mixer: &'a mut Mixer<'a>,
 }
 
@@ -22,18 +166,214 @@ impl<'a> Sfx<'a> {
     pub fn new(mixer: &'a mut Mixer<'a>) -> Self {
         Self {
             mixer,
-            trackers: Vec::new(),
-            bgm: None,
+            music: None,
+            tracker: None,
+            next_music: None,
+            music_volume: 0.into(),
+            duck_volume: 1.into(),
+            ducked: false,
+            settings: AudioSettings::new(),
+            music_events: Vec::new(),
+            beats: 0,
+            fade_frames: MUSIC_FADE_FRAMES,
+            effects: Vec::new(),
+            paused: false,
+            listener: (0, 0).into(),
+            hearing_distance: HEARING_DISTANCE,
         }
     }
 
     pub fn frame(&mut self) {
-        for tracker in self.trackers.iter_mut() {
-            tracker.step(self.mixer);
+        self.music_events.clear();
+        if !self.paused {
+            self.fade_music();
+            self.duck_music();
+            if let Some(tracker) = self.tracker.as_mut() {
+                let volume = self.music_volume * self.duck_volume * self.settings.music_volume();
+                tracker.set_volume(self.mixer, volume);
+                tracker.step(self.mixer);
+
+                if let Some(music) = self.music {
+                    if tracker.row_started() && tracker.current_row() % music.rows_per_beat() == 0 {
+                        self.music_events.push(MusicEvent::Beat(self.beats));
+                        self.beats += 1;
+                    }
+                }
+            }
         }
         self.mixer.frame();
     }
 
+    /// Switch to a different track, fading the current one out first. Carries
+    /// on without a break if the track is already playing.
+    pub fn play_music(&mut self, music: Option<Music>) {
+        if self.next_music.is_none() && self.music == music {
+            return;
+        }
+        if self.tracker.is_none() {
+            self.start_music(music);
+            return;
+        }
+
+        self.next_music = Some(music);
+    }
+
+    /// What happened in the music during the last frame
+    pub fn music_events(&self) -> &[MusicEvent] {
+        &self.music_events
+    }
+
+    /// Stop the music straight away, without fading
+    pub fn stop_music(&mut self) {
+        self.start_music(None);
+    }
+
+    fn start_music(&mut self, music: Option<Music>) {
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.stop(self.mixer);
+        }
+
+        self.music = music;
+        self.next_music = None;
+        self.beats = 0;
+        self.music_volume = 0.into();
+        self.tracker = music.map(|music| {
+            let mut tracker = Tracker::new(music.track());
+            tracker.set_volume(self.mixer, 0.into());
+            tracker
+        });
+    }
+
+    fn fade_music(&mut self) {
+        let step = Num::new(1) / (self.fade_frames.max(1) as i32);
+
+        if let Some(next_music) = self.next_music {
+            self.music_volume -= step;
+            if self.music_volume <= 0.into() {
+                self.start_music(next_music);
+            }
+        } else {
+            self.music_volume = (self.music_volume + step).min(1.into());
+        }
+    }
+
+    /// Bring the music down while dialogue or a ducking effect plays, and back
+    /// up once they finish
+    fn duck_music(&mut self) {
+        self.effects
+            .retain(|effect| self.mixer.channel(&effect.id).is_some());
+        let ducking = self.ducked
+            || self
+                .effects
+                .iter()
+                .any(|effect| effect.sound.info().ducks_music);
+
+        let step = (Num::new(1) - DUCK_VOLUME) / DUCK_FRAMES;
+        self.duck_volume = if ducking {
+            (self.duck_volume - step).max(DUCK_VOLUME)
+        } else {
+            (self.duck_volume + step).min(1.into())
+        };
+    }
+
+    /// Keep the music ducked until this is called again with `false`. Nothing
+    /// calls this yet. It is here for dialogue, which the game doesn't have so
+    /// far. Effects with `ducks_music` duck the music without it.
+    pub fn set_ducked(&mut self, ducked: bool) {
+        self.ducked = ducked;
+    }
+
+    pub fn settings(&self) -> AudioSettings {
+        self.settings
+    }
+
+    /// Change the volume levels. Music picks them up on the next frame and
+    /// effects the next time they play.
+    pub fn set_settings(&mut self, settings: AudioSettings) {
+        self.settings = settings;
+    }
+
+    /// Freeze the music and any sound effects still playing. The tracker
+    /// isn't stepped while paused, and is muted so that held notes don't ring
+    /// on.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        self.paused = true;
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.set_volume(self.mixer, 0.into());
+        }
+        self.effects
+            .retain(|effect| match self.mixer.channel(&effect.id) {
+                Some(channel) => {
+                    channel.pause();
+                    true
+                }
+                None => false,
+            });
+    }
+
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+
+        // The music's volume comes back on the next frame
+        self.paused = false;
+        for effect in self.effects.iter() {
+            if let Some(channel) = self.mixer.channel(&effect.id) {
+                channel.resume();
+            }
+        }
+    }
+
+    /// Start a sound effect, first making room for it by cutting off an older
+    /// instance of the same sound or a lower priority one. Does nothing if
+    /// every voice is playing something more important.
+    fn play_effect(&mut self, sound: Sound, channel: SoundChannel) {
+        self.effects
+            .retain(|effect| self.mixer.channel(&effect.id).is_some());
+
+        let info = sound.info();
+        let instances = self
+            .effects
+            .iter()
+            .filter(|effect| effect.sound == sound)
+            .count();
+
+        let stolen = if instances >= info.max_instances.max(1) as usize {
+            self.effects.iter().position(|effect| effect.sound == sound)
+        } else if self.effects.len() >= EFFECT_VOICES {
+            let lowest = self
+                .effects
+                .iter()
+                .map(|effect| effect.sound.info().priority)
+                .min()
+                .unwrap_or(0);
+            if lowest > info.priority {
+                return;
+            }
+            self.effects
+                .iter()
+                .position(|effect| effect.sound.info().priority == lowest)
+        } else {
+            None
+        };
+
+        if let Some(index) = stolen {
+            let effect = self.effects.remove(index);
+            if let Some(channel) = self.mixer.channel(&effect.id) {
+                channel.stop();
+            }
+        }
+
+        if let Some(id) = self.mixer.play_sound(channel) {
+            self.effects.push(Effect { id, sound });
+        }
+    }
+
     pub fn tink(&mut self) {
         // We can play a sample
         if let Some(sample) = CRAWL_XM.samples.first() {
@@ -42,32 +382,68 @@ impl<'a> Sfx<'a> {
         }
     }
 
-    pub fn stop_music(&mut self) {
-        if let Some(bgm) = &self.bgm {
-            let channel = self.mixer.channel(bgm).unwrap();
-            channel.stop();
+    /// Play a random variation of a sound with its volume, priority and a
+    /// random pitch from its range
+    pub fn play(&mut self, sound: Sound) {
+        if let Some(channel) = sound_channel(sound, self.settings.effects_volume()) {
+            self.play_effect(sound, channel);
         }
-        self.trackers.clear();
-        self.bgm = None;
     }
 
-    pub fn crawl(&mut self) {
-        self.trackers.push(Tracker::new(&CRAWL_XM));
+    /// Set where positional sounds are heard from
+    pub fn set_listener(&mut self, listener: Vector2D<i32>) {
+        self.listener = listener;
     }
 
-    pub fn bat_flap(&mut self) {
-        self.mixer.play_sound(SoundChannel::new(BAT_FLAP));
-    }
+    /// Play a sound coming from somewhere in the world, panned towards the side
+    /// it is on and quieter the further it is from the listener
+    pub fn play_at(&mut self, sound: Sound, position: Vector2D<Num<i32, 8>>) {
+        let offset = position.floor() - self.listener;
+        let distance = offset.x.abs().max(offset.y.abs());
+        if distance >= self.hearing_distance {
+            return;
+        }
 
-    pub fn jump(&mut self) {
-        let r = rng::gen() % 3;
+        let fade_distance = (self.hearing_distance - FULL_VOLUME_DISTANCE).max(1);
+        let faded = (distance - FULL_VOLUME_DISTANCE).clamp(0, fade_distance);
+        let attenuation = Num::<i32, 8>::new(fade_distance - faded) / fade_distance;
+        let panning = Num::<i32, 8>::new(offset.x.clamp(-FULL_PAN_DISTANCE, FULL_PAN_DISTANCE))
+            / FULL_PAN_DISTANCE;
 
-        let channel = match r {
-            0 => SoundChannel::new(JUMP1),
-            1 => SoundChannel::new(JUMP2),
-            _ => SoundChannel::new(JUMP3),
+        let volume = self.settings.effects_volume() * attenuation;
+        let Some(mut channel) = sound_channel(sound, volume) else {
+            return;
         };
+        channel.panning(Num::from_raw(panning.to_raw() as i16));
+
+        self.play_effect(sound, channel);
+    }
+}
 
-        self.mixer.play_sound(channel);
+/// A channel for a random variation of the sound, at its volume scaled by
+/// `volume`
+fn sound_channel(sound: Sound, volume: Num<i32, 8>) -> Option<SoundChannel> {
+    let info = sound.info();
+    let data = random_choice(info.variations)?;
+
+    // Effects are limited to `EFFECT_VOICES`, so they can always take a
+    // channel from the music
+    let mut channel = SoundChannel::new_high_priority(data);
+    channel
+        .volume(info.volume * Num::from_raw(volume.to_raw() as i16))
+        .playback(random_pitch(info.pitch));
+
+    Some(channel)
+}
+
+fn random_choice<T: Copy>(items: &[T]) -> Option<T> {
+    if items.is_empty() {
+        return None;
     }
+    Some(items[rng::gen().unsigned_abs() as usize % items.len()])
+}
+
+fn random_pitch((min, max): (Num<u32, 8>, Num<u32, 8>)) -> Num<u32, 8> {
+    let range = (max - min).to_raw() + 1;
+    min + Num::from_raw(rng::gen().unsigned_abs() % range)
 }
@@ -0,0 +1,52 @@
+use agb::fixnum::{Rect, Vector2D};
+
+/// The hurtbox, hitbox and pivot of every frame of an aseprite tag, taken from
+/// the slices in the aseprite file. Coordinates are relative to the top left of
+/// an unflipped frame.
+pub struct SpriteBoxes {
+    pub size: Vector2D<i32>,
+    pub frames: &'static [FrameBoxes],
+}
+
+pub struct FrameBoxes {
+    pub pivot: Vector2D<i32>,
+    pub hurtbox: Option<Rect<i32>>,
+    pub hitbox: Option<Rect<i32>>,
+}
+
+impl SpriteBoxes {
+    pub fn frame(&self, idx: usize) -> &FrameBoxes {
+        &self.frames[idx % self.frames.len()]
+    }
+
+    pub fn pivot(&self, frame: &FrameBoxes, hflip: bool) -> Vector2D<i32> {
+        if hflip {
+            Vector2D::new(self.size.x - frame.pivot.x, frame.pivot.y)
+        } else {
+            frame.pivot
+        }
+    }
+
+    pub fn mirror(&self, rect: Rect<i32>, hflip: bool) -> Rect<i32> {
+        if hflip {
+            Rect {
+                position: Vector2D::new(
+                    self.size.x - rect.position.x - rect.size.x,
+                    rect.position.y,
+                ),
+                size: rect.size,
+            }
+        } else {
+            rect
+        }
+    }
+}
+
+// Boxes are generated for every tag, whether or not an entity uses them
+#[allow(dead_code)]
+pub mod boxes {
+    use super::{FrameBoxes, SpriteBoxes};
+    use agb::fixnum::{Rect, Vector2D};
+
+    include!(concat!(env!("OUT_DIR"), "/sprite_boxes.rs"));
+}